@@ -4,8 +4,9 @@ use models::IncomingMessage;
 use redis::Commands;
 use services::redis_manager::RedisManager;
 use sqlx::PgPool;
+use std::collections::HashSet;
 use time::OffsetDateTime;
-use tracing::info;
+use tracing::{info, warn};
 
 mod models;
 mod services;
@@ -19,6 +20,10 @@ async fn main() -> Result<()> {
     let mut conn = redis_manager.get_connection()?;
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPool::connect(&database_url).await?;
+    let mut known_tables: HashSet<String> = MIGRATED_PRICE_TABLES
+        .iter()
+        .map(|(_, table)| table.to_string())
+        .collect();
 
     loop {
         let response: Option<(String, String)> = conn.brpop("db_processor", 0.0)?;
@@ -36,36 +41,96 @@ async fn main() -> Result<()> {
                         data.price, data.time
                     );
 
-                    match data.ticker.as_str() {
-                        "SOL_USDC" => {
-                            sqlx::query!(
-                                "INSERT INTO sol_prices (time, price, currency_code) VALUES ($1, $2, $3)", 
-                                OffsetDateTime::from_unix_timestamp(data.time.timestamp()).unwrap(),
-                                data.price.to_string().parse::<f64>().unwrap(),
-                                "SOL"
-                            ).execute(&pool).await?;
-                        }
-                        "BTC_USDC" => {
-                            sqlx::query!(
-                                "INSERT INTO btc_prices (time, price, currency_code) VALUES ($1, $2, $3)", 
-                                OffsetDateTime::from_unix_timestamp(data.time.timestamp()).unwrap(),
-                                data.price.to_string().parse::<f64>().unwrap(),
-                                "BTC"
-                            ).execute(&pool).await?;
-                        }
-                        "ETH_USDC" => {
-                            sqlx::query!(
-                                "INSERT INTO eth_prices (time, price, currency_code) VALUES ($1, $2, $3)", 
-                                OffsetDateTime::from_unix_timestamp(data.time.timestamp()).unwrap(),
-                                data.price.to_string().parse::<f64>().unwrap(),
-                                "ETH"
-                            ).execute(&pool).await?;
-                        }
-                        _ => {}
+                    let Some(table) = price_table(&data.ticker) else {
+                        warn!(ticker = ?data.ticker, "Skipping trade for invalid ticker");
+                        continue;
+                    };
+                    let asset = data.ticker.split('_').next().unwrap_or_default();
+
+                    if !known_tables.contains(&table) {
+                        ensure_price_table(&pool, &table).await?;
+                        known_tables.insert(table.clone());
                     }
+
+                    sqlx::query(&format!(
+                        "INSERT INTO {} (time, price, currency_code) VALUES ($1, $2, $3)",
+                        table
+                    ))
+                    .bind(OffsetDateTime::from_unix_timestamp(data.time.timestamp()).unwrap())
+                    .bind(data.price.to_string().parse::<f64>().unwrap())
+                    .bind(asset)
+                    .execute(&pool)
+                    .await?;
                 }
             }
             None => {}
         }
     }
 }
+
+/// The built-in markets keep the tables their migrations created.
+const MIGRATED_PRICE_TABLES: [(&str, &str); 3] = [
+    ("SOL_USDC", "sol_prices"),
+    ("BTC_USDC", "btc_prices"),
+    ("ETH_USDC", "eth_prices"),
+];
+
+/// The klines views built over each price table, as in the migrations.
+const KLINE_INTERVALS: [(&str, &str); 3] = [("1m", "1 minute"), ("1h", "1 hour"), ("1w", "1 week")];
+
+/// Only alphanumeric assets are accepted, as they end up in SQL.
+fn price_table(ticker: &str) -> Option<String> {
+    if let Some((_, table)) = MIGRATED_PRICE_TABLES
+        .iter()
+        .find(|(market, _)| *market == ticker)
+    {
+        return Some(table.to_string());
+    }
+    let (base, quote) = ticker.split_once('_')?;
+    let valid = |asset: &str| !asset.is_empty() && asset.chars().all(|c| c.is_ascii_alphanumeric());
+    (valid(base) && valid(quote)).then(|| format!("{}_{}_prices", base, quote).to_lowercase())
+}
+
+async fn ensure_price_table(pool: &PgPool, table: &str) -> Result<()> {
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            time    TIMESTAMP WITH TIME ZONE NOT NULL,
+            price   DOUBLE PRECISION,
+            volume  DOUBLE PRECISION,
+            currency_code   VARCHAR(10)
+        )",
+        table
+    ))
+    .execute(pool)
+    .await?;
+
+    sqlx::query(&format!(
+        "SELECT create_hypertable('{}', 'time', 'price', 2, if_not_exists => TRUE)",
+        table
+    ))
+    .execute(pool)
+    .await?;
+
+    let market = table.trim_end_matches("_prices");
+    for (suffix, interval) in KLINE_INTERVALS {
+        sqlx::query(&format!(
+            "CREATE MATERIALIZED VIEW IF NOT EXISTS {}_klines_{} AS
+            SELECT
+                time_bucket('{}', time) AS bucket,
+                first(price, time) AS open,
+                max(price) AS high,
+                min(price) AS low,
+                last(price, time) AS close,
+                sum(volume) AS volume,
+                currency_code
+            FROM {}
+            GROUP BY bucket, currency_code",
+            market, suffix, interval, table
+        ))
+        .execute(pool)
+        .await?;
+    }
+
+    info!(table, "Ensured price table");
+    Ok(())
+}
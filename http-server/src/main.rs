@@ -1,5 +1,6 @@
 use axum::{
-    routing::{delete, get, post},
+    middleware,
+    routing::{delete, get, patch, post},
     Router,
 };
 
@@ -25,8 +26,13 @@ async fn main() {
                 .nest(
                     "/order",
                     Router::new()
+                        .route("/", patch(routes::amend_order))
                         .route("/create", post(routes::create_order))
+                        .route("/close-position", post(routes::close_position))
+                        .route("/add-collateral", post(routes::add_collateral))
+                        .route("/remove-collateral", post(routes::remove_collateral))
                         .route("/cancel", delete(routes::cancel_order))
+                        .route("/cancel-all", delete(routes::cancel_all_orders))
                         .route("/open", get(routes::open_orders))
                         .route("/quote", post(routes::get_quote))
                         .route("/margin-positions", get(routes::margin_positions)),
@@ -35,10 +41,24 @@ async fn main() {
                     "/user",
                     Router::new()
                         .route("/balances", get(routes::get_balances))
-                        .route("/onramp", post(routes::onramp)),
+                        .route("/account", get(routes::get_account))
+                        .route("/onramp", post(routes::onramp))
+                        .route("/margin-mode", post(routes::set_margin_mode)),
+                )
+                .nest(
+                    "/admin",
+                    Router::new()
+                        .route("/markets", post(routes::create_market))
+                        .route("/markets/status", post(routes::set_market_status))
+                        .route_layer(middleware::from_fn_with_state(
+                            app_state.clone(),
+                            routes::require_admin_token,
+                        )),
                 )
                 .route("/depth", get(routes::get_depth))
-                .route("/ticker", get(routes::get_ticker)),
+                .route("/markets", get(routes::get_markets))
+                .route("/ticker", get(routes::get_ticker))
+                .route("/funding", get(routes::get_funding_history)),
         )
         .with_state(app_state);
 
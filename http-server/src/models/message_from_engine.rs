@@ -1,4 +1,4 @@
-use super::OrderSide;
+use super::{MarginMode, MarketStatus, OrderSide, OrderType, ProductType, SelfTradePrevention};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -12,6 +12,10 @@ pub enum MessageFromEngine {
     OrderPlaced { payload: OrderPlacedPayload },
     #[serde(rename = "ORDER_CANCELLED")]
     OrderCancelled { payload: OrderCancelledPayload },
+    #[serde(rename = "ORDER_AMENDED")]
+    OrderAmended { payload: OrderAmendedPayload },
+    #[serde(rename = "ORDERS_CANCELLED")]
+    OrdersCancelled { payload: OrdersCancelledPayload },
     #[serde(rename = "OPEN_ORDERS")]
     OpenOrders { payload: OpenOrdersPayload },
     #[serde(rename = "USER_BALANCES")]
@@ -20,11 +24,55 @@ pub enum MessageFromEngine {
     GetMarginPositions { payload: MarginPositionsPayload },
     #[serde(rename = "SEND_QUOTE")]
     SendQuote { payload: GetQuoteResponse },
+    #[serde(rename = "MARKET_CREATED")]
+    MarketCreated { payload: MarketSpec },
+    #[serde(rename = "MARKET_STATUS")]
+    MarketStatus { payload: MarketStatusPayload },
+    #[serde(rename = "MARKETS")]
+    Markets { payload: MarketsPayload },
+    #[serde(rename = "ERROR")]
+    Error { payload: ErrorPayload },
     #[serde(rename = "TICKER_PRICE")]
-    TickerPrice {
-        market: String,
-        price: Option<PriceInfo>,
-    },
+    TickerPrice { payload: TickerPayload },
+    #[serde(rename = "FUNDING_HISTORY")]
+    FundingHistory { payload: FundingHistoryPayload },
+    #[serde(rename = "MARGIN_MODE")]
+    MarginMode { payload: MarginModePayload },
+    #[serde(rename = "COLLATERAL_ADJUSTED")]
+    CollateralAdjusted { payload: CollateralAdjustedPayload },
+    #[serde(rename = "ACCOUNT")]
+    Account { payload: AccountPayload },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarginModePayload {
+    pub user_id: String,
+    pub market: String,
+    pub mode: MarginMode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollateralAdjustedPayload {
+    pub position: MarginPosition,
+    pub liquidation_price: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickerPayload {
+    pub market: String,
+    pub price: Option<PriceInfo>,
+    pub stats: Stats24h,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stats24h {
+    pub open: Option<Decimal>,
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+    pub close: Option<Decimal>,
+    pub base_volume: Decimal,
+    pub quote_volume: Decimal,
+    pub trade_count: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +96,12 @@ pub struct MarginPosition {
     pub leverage: Decimal,
     pub collateral: Decimal,
     pub unrealized_pnl: Decimal,
+    #[serde(default)]
+    pub adl_rank: Option<u32>,
+    #[serde(default)]
+    pub liquidation_price: Option<Decimal>,
+    #[serde(default)]
+    pub margin_ratio: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,6 +117,15 @@ pub struct Order {
     pub quantity: Decimal,
     pub side: OrderSide,
     pub timestamp: i64,
+    pub order_type: OrderType,
+    pub trigger_price: Option<Decimal>,
+    pub iceberg: Option<Iceberg>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Iceberg {
+    pub display_quantity: Decimal,
+    pub visible_quantity: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -81,6 +144,45 @@ pub struct OrderPlacedPayload {
     pub order_id: String,
     pub remaining_qty: Decimal,
     pub filled_qty: Decimal,
+    pub fee: Decimal,
+    pub fee_asset: String,
+    pub fills: Vec<Fill>,
+    pub prevented_matches: Vec<PreventedMatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fill {
+    pub trade_id: String,
+    pub market: String,
+    pub maker_order_id: String,
+    pub taker_order_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub taker_side: OrderSide,
+    pub maker_fee: Decimal,
+    pub maker_fee_asset: String,
+    pub taker_fee: Decimal,
+    pub taker_fee_asset: String,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreventedMatch {
+    pub maker_order_id: String,
+    pub quantity: Decimal,
+    pub mode: SelfTradePrevention,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderAmendedPayload {
+    pub order_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrdersCancelledPayload {
+    pub order_ids: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -100,6 +202,57 @@ pub struct UserBalancesPayload {
     pub balances: Vec<Balance>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BalanceValue {
+    pub ticker: String,
+    pub balance: Decimal,
+    pub locked_balance: Decimal,
+    pub value: Option<Decimal>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountPayload {
+    pub user_id: String,
+    pub balances: Vec<BalanceValue>,
+    pub balance_value: Decimal,
+    pub unrealized_pnl: Decimal,
+    pub realized_pnl: Decimal,
+    pub equity: Decimal,
+    pub position_value: Decimal,
+    pub initial_margin: Decimal,
+    pub maintenance_margin: Decimal,
+    pub free_collateral: Decimal,
+    pub leverage: Option<Decimal>,
+    pub margin_calls: Vec<MarginCallEvent>,
+    pub rejected_triggers: Vec<TriggerRejectedEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MarginCallEvent {
+    pub market: String,
+    pub user_id: String,
+    pub position_type: PositionType,
+    pub threshold: Decimal,
+    pub equity: Decimal,
+    pub maintenance_margin: Decimal,
+    pub mark_price: Decimal,
+    pub liquidation_price: Option<Decimal>,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TriggerRejectedEvent {
+    pub order_id: String,
+    pub user_id: String,
+    pub market: String,
+    pub order_type: OrderType,
+    pub side: OrderSide,
+    pub quantity: Decimal,
+    pub trigger_price: Option<Decimal>,
+    pub reason: String,
+    pub timestamp: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GetQuoteResponse {
     pub avg_price: Decimal,
@@ -114,3 +267,87 @@ pub struct PriceInfo {
     pub index_price: Option<Decimal>,
     pub timestamp: i64,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MarketsPayload {
+    pub markets: Vec<MarketSpec>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MarketSpec {
+    pub market: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    pub min_quantity: Decimal,
+    pub min_notional: Decimal,
+    pub max_order_size: Decimal,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub price_band: PriceBand,
+    pub fees: FeeSchedule,
+    pub max_basis_pct: Decimal,
+    pub product: ProductType,
+    pub funding: FundingConfig,
+    pub liquidation: LiquidationConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LiquidationConfig {
+    pub maintenance_margin_rate: Decimal,
+    pub fee_rate: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FundingConfig {
+    pub interval_secs: i64,
+    pub max_rate: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FundingHistoryPayload {
+    pub market: String,
+    pub history: Vec<FundingRate>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FundingRate {
+    pub market: String,
+    pub rate: Decimal,
+    pub premium_index: Decimal,
+    pub mark_price: Decimal,
+    pub index_price: Decimal,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceBand {
+    pub band_pct: Decimal,
+    pub halt_move_pct: Decimal,
+    pub window_secs: i64,
+    pub cooldown_secs: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeeSchedule {
+    pub tiers: Vec<FeeTier>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorPayload {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MarketStatusPayload {
+    pub market: String,
+    pub status: MarketStatus,
+    pub reason: Option<String>,
+}
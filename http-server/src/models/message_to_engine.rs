@@ -1,4 +1,6 @@
-use super::{OrderSide, OrderType};
+use super::{
+    MarginMode, MarketStatus, OrderSide, OrderType, ProductType, SelfTradePrevention, TimeInForce,
+};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
@@ -7,8 +9,18 @@ use serde::{Deserialize, Serialize};
 pub enum MessageToEngine {
     #[serde(rename = "CREATE_ORDER")]
     CreateOrder { data: CreateOrderPayload },
+    #[serde(rename = "CLOSE_POSITION")]
+    ClosePosition { data: ClosePositionPayload },
+    #[serde(rename = "ADD_COLLATERAL")]
+    AddCollateral { data: AdjustCollateralPayload },
+    #[serde(rename = "REMOVE_COLLATERAL")]
+    RemoveCollateral { data: AdjustCollateralPayload },
     #[serde(rename = "CANCEL_ORDER")]
     CancelOrder { data: CancelOrderPayload },
+    #[serde(rename = "AMEND_ORDER")]
+    AmendOrder { data: AmendOrderPayload },
+    #[serde(rename = "CANCEL_ALL_ORDERS")]
+    CancelAllOrders { data: CancelAllOrdersPayload },
     #[serde(rename = "GET_DEPTH")]
     GetDepth { data: GetDepthPayload },
     #[serde(rename = "GET_OPEN_ORDERS")]
@@ -19,23 +31,60 @@ pub enum MessageToEngine {
     GetUserBalances { data: GetUserBalancesPayload },
     #[serde(rename = "GET_MARGIN_POSITIONS")]
     GetMarginPositions { data: GetMarginPositionsPayload },
+    #[serde(rename = "GET_ACCOUNT")]
+    GetAccount { data: GetAccountPayload },
+    #[serde(rename = "CREATE_MARKET")]
+    CreateMarket { data: CreateMarketPayload },
+    #[serde(rename = "SET_MARKET_STATUS")]
+    SetMarketStatus { data: SetMarketStatusPayload },
+    #[serde(rename = "GET_MARKETS")]
+    GetMarkets,
     #[serde(rename = "GET_TICKER")]
     GetTicker {
         market: String,
         order_type: OrderType,
     },
+    #[serde(rename = "GET_FUNDING_HISTORY")]
+    GetFundingHistory { market: String },
+    #[serde(rename = "SET_MARGIN_MODE")]
+    SetMarginMode { data: SetMarginModePayload },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateOrderPayload {
     pub user_id: String,
     pub market: String,
+    #[serde(default)]
     pub price: Decimal,
     pub quantity: Decimal,
     pub side: OrderSide,
     pub order_type: OrderType,
     pub is_margin: bool,
     pub leverage: Option<Decimal>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    pub trigger_price: Option<Decimal>,
+    pub display_quantity: Option<Decimal>,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// Margin orders that may only shrink the opposite position.
+    #[serde(default)]
+    pub reduce_only: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClosePositionPayload {
+    pub user_id: String,
+    pub market: String,
+    pub position_type: OrderType,
+    /// Closes the whole position when not set.
+    pub quantity: Option<Decimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdjustCollateralPayload {
+    pub user_id: String,
+    pub market: String,
+    pub amount: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,6 +94,22 @@ pub struct CancelOrderPayload {
     pub market: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelAllOrdersPayload {
+    pub user_id: String,
+    pub market: Option<String>,
+    pub side: Option<OrderSide>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AmendOrderPayload {
+    pub order_id: String,
+    pub user_id: String,
+    pub market: String,
+    pub price: Option<Decimal>,
+    pub quantity: Option<Decimal>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetDepthPayload {
     pub market: String,
@@ -74,3 +139,34 @@ pub struct GetUserBalancesPayload {
 pub struct GetMarginPositionsPayload {
     pub user_id: String,
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetAccountPayload {
+    pub user_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateMarketPayload {
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: Option<Decimal>,
+    pub lot_size: Option<Decimal>,
+    pub min_quantity: Option<Decimal>,
+    pub min_notional: Option<Decimal>,
+    pub max_order_size: Option<Decimal>,
+    pub product: Option<ProductType>,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetMarketStatusPayload {
+    pub market: String,
+    pub status: MarketStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetMarginModePayload {
+    pub user_id: String,
+    pub market: String,
+    pub mode: MarginMode,
+}
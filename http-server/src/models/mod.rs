@@ -11,6 +11,10 @@ pub enum OrderType {
     MarginLong,
     MarginShort,
     Spot,
+    Market,
+    StopMarket,
+    StopLimit,
+    TakeProfit,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,6 +23,44 @@ pub enum OrderSide {
     Sell,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub enum TimeInForce {
+    #[default]
+    Gtc,
+    Ioc,
+    Fok,
+    PostOnly,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MarketStatus {
+    Trading,
+    Halted,
+    CancelOnly,
+    PostOnly,
+    Delisted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MarginMode {
+    Isolated,
+    Cross,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ProductType {
+    Spot,
+    Perpetual,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SelfTradePrevention {
+    CancelNewest,
+    CancelOldest,
+    CancelBoth,
+    DecrementAndCancel,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OnRampPayload {
     pub user_id: String,
@@ -42,6 +84,11 @@ pub struct GetUserBalancesQuery {
     pub user_id: String,
 }
 
+#[derive(Deserialize)]
+pub struct GetAccountQuery {
+    pub user_id: String,
+}
+
 #[derive(Deserialize)]
 pub struct GetDepthQuery {
     pub market: String,
@@ -53,3 +100,8 @@ pub struct GetTickerQuery {
     pub market: String,
     pub order_type: OrderType,
 }
+
+#[derive(Deserialize)]
+pub struct GetFundingHistoryQuery {
+    pub market: String,
+}
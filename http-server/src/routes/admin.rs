@@ -0,0 +1,61 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    models::{CreateMarketPayload, MessageToEngine, SetMarketStatusPayload},
+    state::AppState,
+};
+
+pub async fn require_admin_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get("x-admin-token")
+        .and_then(|value| value.to_str().ok());
+
+    match (state.admin_token.as_deref(), provided) {
+        (Some(token), Some(provided)) if token == provided => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Invalid admin token" })),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn create_market(
+    State(state): State<AppState>,
+    Json(market_data): Json<CreateMarketPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::CreateMarket { data: market_data };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+pub async fn set_market_status(
+    State(state): State<AppState>,
+    Json(status_data): Json<SetMarketStatusPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::SetMarketStatus { data: status_data };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
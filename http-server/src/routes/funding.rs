@@ -0,0 +1,26 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    models::{GetFundingHistoryQuery, MessageToEngine},
+    state::AppState,
+};
+
+pub async fn get_funding_history(
+    State(state): State<AppState>,
+    Query(params): Query<GetFundingHistoryQuery>,
+) -> Json<Value> {
+    let message = MessageToEngine::GetFundingHistory {
+        market: params.market,
+    };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
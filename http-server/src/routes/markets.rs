@@ -0,0 +1,15 @@
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+
+use crate::{models::MessageToEngine, state::AppState};
+
+pub async fn get_markets(State(state): State<AppState>) -> Json<Value> {
+    let message = MessageToEngine::GetMarkets;
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
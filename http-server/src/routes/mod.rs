@@ -9,3 +9,12 @@ pub use user::*;
 
 pub mod ticker;
 pub use ticker::*;
+
+pub mod markets;
+pub use markets::*;
+
+pub mod admin;
+pub use admin::*;
+
+pub mod funding;
+pub use funding::*;
@@ -6,8 +6,10 @@ use serde_json::{json, Value};
 
 use crate::{
     models::{
-        CancelOrderPayload, CreateOrderPayload, GetMarginPositionsPayload, GetMarginPositionsQuery,
-        GetOpenOrdersPayload, GetQuoteRequest, MessageToEngine, OpenOrdersQuery,
+        AdjustCollateralPayload, AmendOrderPayload, CancelAllOrdersPayload, CancelOrderPayload,
+        ClosePositionPayload, CreateOrderPayload, GetMarginPositionsPayload,
+        GetMarginPositionsQuery, GetOpenOrdersPayload, GetQuoteRequest, MessageToEngine,
+        OpenOrdersQuery,
     },
     state::AppState,
 };
@@ -26,6 +28,52 @@ pub async fn create_order(
     }
 }
 
+pub async fn close_position(
+    State(state): State<AppState>,
+    Json(close_data): Json<ClosePositionPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::ClosePosition { data: close_data };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+pub async fn add_collateral(
+    State(state): State<AppState>,
+    Json(collateral_data): Json<AdjustCollateralPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::AddCollateral {
+        data: collateral_data,
+    };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+pub async fn remove_collateral(
+    State(state): State<AppState>,
+    Json(collateral_data): Json<AdjustCollateralPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::RemoveCollateral {
+        data: collateral_data,
+    };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
 pub async fn cancel_order(
     State(state): State<AppState>,
     Json(order_data): Json<CancelOrderPayload>,
@@ -40,6 +88,34 @@ pub async fn cancel_order(
     }
 }
 
+pub async fn cancel_all_orders(
+    State(state): State<AppState>,
+    Json(order_data): Json<CancelAllOrdersPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::CancelAllOrders { data: order_data };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
+pub async fn amend_order(
+    State(state): State<AppState>,
+    Json(order_data): Json<AmendOrderPayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::AmendOrder { data: order_data };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
 pub async fn get_quote(
     State(state): State<AppState>,
     Json(quote_data): Json<GetQuoteRequest>,
@@ -6,7 +6,10 @@ use serde_json::{json, Value};
 use tracing::info;
 
 use crate::{
-    models::{GetUserBalancesPayload, GetUserBalancesQuery, MessageToEngine, OnRampPayload},
+    models::{
+        GetAccountPayload, GetAccountQuery, GetUserBalancesPayload, GetUserBalancesQuery,
+        MessageToEngine, OnRampPayload, SetMarginModePayload,
+    },
     state::AppState,
 };
 
@@ -28,6 +31,24 @@ pub async fn get_balances(
     }
 }
 
+pub async fn get_account(
+    State(state): State<AppState>,
+    Query(params): Query<GetAccountQuery>,
+) -> Json<Value> {
+    let message = MessageToEngine::GetAccount {
+        data: GetAccountPayload {
+            user_id: params.user_id,
+        },
+    };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
+
 pub async fn onramp(
     State(state): State<AppState>,
     Json(payload): Json<OnRampPayload>,
@@ -44,3 +65,17 @@ pub async fn onramp(
         })),
     }
 }
+
+pub async fn set_margin_mode(
+    State(state): State<AppState>,
+    Json(mode_data): Json<SetMarginModePayload>,
+) -> Json<Value> {
+    let message = MessageToEngine::SetMarginMode { data: mode_data };
+
+    match state.redis_manager.send_and_wait(message) {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({
+            "error": format!("Redis error: {}", e)
+        })),
+    }
+}
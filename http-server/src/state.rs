@@ -5,12 +5,15 @@ use crate::services::redis_manager::RedisManager;
 #[derive(Clone)]
 pub struct AppState {
     pub redis_manager: Arc<RedisManager>,
+    /// Token required by the admin routes, which are refused while it is unset.
+    pub admin_token: Option<Arc<str>>,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             redis_manager: Arc::new(RedisManager::new()),
+            admin_token: std::env::var("ADMIN_TOKEN").ok().map(Arc::from),
         }
     }
 }
@@ -54,7 +54,8 @@ async fn order_loop() -> Result<()> {
 
         while bids_to_add > 0 || asks_to_add > 0 {
             if bids_to_add > 0 {
-                let bid_price = price - Decimal::from_f64(rng.random::<f64>() * 1.0).unwrap();
+                let bid_price =
+                    (price - Decimal::from_f64(rng.random::<f64>() * 1.0).unwrap()).round_dp(2);
                 let response = client
                     .post(format!("{}/api/v1/order/create", BASE_URL))
                     .json(&CreateOrderPayload {
@@ -75,7 +76,8 @@ async fn order_loop() -> Result<()> {
             }
 
             if asks_to_add > 0 {
-                let ask_price = price + Decimal::from_f64(rng.random::<f64>() * 1.0).unwrap();
+                let ask_price =
+                    (price + Decimal::from_f64(rng.random::<f64>() * 1.0).unwrap()).round_dp(2);
                 client
                     .post(format!("{}/api/v1/order/create", BASE_URL))
                     .json(&CreateOrderPayload {
@@ -1 +1,5 @@
 pub const MESSAGE_FROM_API_CHANNEL: &str = "messages";
+pub const MARKETS_CONFIG_ENV: &str = "MARKETS_CONFIG";
+pub const EXCHANGE_FEE_ACCOUNT: &str = "exchange_fees";
+pub const INDEX_SOURCES_ENV: &str = "INDEX_SOURCES";
+pub const INSURANCE_FUND_ACCOUNT: &str = "insurance_fund";
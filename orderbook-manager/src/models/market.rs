@@ -0,0 +1,285 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+use super::SelfTradePrevention;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum MarketStatus {
+    #[default]
+    Trading,
+    Halted,
+    CancelOnly,
+    PostOnly,
+    Delisted,
+}
+
+impl MarketStatus {
+    pub fn allows_new_orders(self) -> bool {
+        matches!(self, MarketStatus::Trading | MarketStatus::PostOnly)
+    }
+
+    pub fn allows_cancels(self) -> bool {
+        matches!(
+            self,
+            MarketStatus::Trading | MarketStatus::PostOnly | MarketStatus::CancelOnly
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ProductType {
+    #[default]
+    Spot,
+    Perpetual,
+}
+
+/// Funding settled every `interval_secs`, capped at `max_rate`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FundingConfig {
+    pub interval_secs: i64,
+    pub max_rate: Decimal,
+}
+
+impl Default for FundingConfig {
+    fn default() -> Self {
+        FundingConfig {
+            interval_secs: 8 * 60 * 60,
+            max_rate: dec!(0.0075),
+        }
+    }
+}
+
+/// Liquidation and margin call thresholds of a market.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidationConfig {
+    pub maintenance_margin_rate: Decimal,
+    pub fee_rate: Decimal,
+    #[serde(default = "default_margin_call_thresholds")]
+    pub margin_call_thresholds: Vec<Decimal>,
+}
+
+impl Default for LiquidationConfig {
+    fn default() -> Self {
+        LiquidationConfig {
+            maintenance_margin_rate: dec!(0.05),
+            fee_rate: dec!(0.01),
+            margin_call_thresholds: default_margin_call_thresholds(),
+        }
+    }
+}
+
+fn default_margin_call_thresholds() -> Vec<Decimal> {
+    vec![dec!(0.5), dec!(0.75)]
+}
+
+/// Price band for limit orders and the circuit breaker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceBand {
+    pub band_pct: Decimal,
+    pub halt_move_pct: Decimal,
+    pub window_secs: i64,
+    pub cooldown_secs: i64,
+}
+
+impl Default for PriceBand {
+    fn default() -> Self {
+        PriceBand {
+            band_pct: dec!(0.5),
+            halt_move_pct: dec!(0.3),
+            window_secs: 60,
+            cooldown_secs: 300,
+        }
+    }
+}
+
+/// A fee tier. A negative `maker_rate` is a rebate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    /// Rates of the highest tier reached by `volume`.
+    pub fn rates(&self, volume: Decimal) -> (Decimal, Decimal) {
+        self.tiers
+            .iter()
+            .filter(|tier| volume >= tier.min_volume)
+            .max_by_key(|tier| tier.min_volume)
+            .map_or((Decimal::ZERO, Decimal::ZERO), |tier| {
+                (tier.maker_rate, tier.taker_rate)
+            })
+    }
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        let tier = |min_volume, maker_rate, taker_rate| FeeTier {
+            min_volume,
+            maker_rate,
+            taker_rate,
+        };
+        FeeSchedule {
+            tiers: vec![
+                tier(dec!(0), dec!(0.001), dec!(0.002)),
+                tier(dec!(100_000), dec!(0.0005), dec!(0.0015)),
+                tier(dec!(1_000_000), dec!(0), dec!(0.001)),
+                tier(dec!(10_000_000), dec!(-0.0001), dec!(0.0008)),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketSpec {
+    pub market: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    pub min_quantity: Decimal,
+    pub min_notional: Decimal,
+    pub max_order_size: Decimal,
+    /// Self-trade prevention for orders that do not set their own.
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
+    #[serde(default)]
+    pub price_band: PriceBand,
+    #[serde(default)]
+    pub fees: FeeSchedule,
+    /// Maximum basis of the mark over the index price.
+    #[serde(default = "default_max_basis_pct")]
+    pub max_basis_pct: Decimal,
+    #[serde(default)]
+    pub product: ProductType,
+    #[serde(default)]
+    pub funding: FundingConfig,
+    #[serde(default)]
+    pub liquidation: LiquidationConfig,
+}
+
+fn default_max_basis_pct() -> Decimal {
+    dec!(0.01)
+}
+
+impl MarketSpec {
+    pub fn new(base_asset: &str, quote_asset: &str) -> Self {
+        MarketSpec {
+            market: format!("{}_{}", base_asset, quote_asset),
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size: dec!(0.01),
+            lot_size: dec!(0.01),
+            min_quantity: dec!(0.01),
+            min_notional: dec!(1),
+            max_order_size: dec!(1_000_000),
+            self_trade_prevention: SelfTradePrevention::default(),
+            price_band: PriceBand::default(),
+            fees: FeeSchedule::default(),
+            max_basis_pct: default_max_basis_pct(),
+            product: ProductType::default(),
+            funding: FundingConfig::default(),
+            liquidation: LiquidationConfig::default(),
+        }
+    }
+
+    pub fn validate_price(&self, price: Decimal) -> Result<(), String> {
+        if price <= Decimal::ZERO {
+            return Err(format!("Price {} must be positive", price));
+        }
+        if !(price % self.tick_size).is_zero() {
+            return Err(format!(
+                "Price {} is not a multiple of the tick size {}",
+                price, self.tick_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_quantity(&self, quantity: Decimal) -> Result<(), String> {
+        if quantity < self.min_quantity {
+            return Err(format!(
+                "Quantity {} is below the minimum of {}",
+                quantity, self.min_quantity
+            ));
+        }
+        if quantity > self.max_order_size {
+            return Err(format!(
+                "Quantity {} exceeds the maximum order size of {}",
+                quantity, self.max_order_size
+            ));
+        }
+        if !(quantity % self.lot_size).is_zero() {
+            return Err(format!(
+                "Quantity {} is not a multiple of the lot size {}",
+                quantity, self.lot_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Market orders carry no price, so only their quantity is checked.
+    pub fn validate_order(&self, price: Option<Decimal>, quantity: Decimal) -> Result<(), String> {
+        self.validate_quantity(quantity)?;
+
+        if let Some(price) = price {
+            self.validate_price(price)?;
+
+            let notional = price * quantity;
+            if notional < self.min_notional {
+                return Err(format!(
+                    "Order notional {} is below the minimum of {}",
+                    notional, self.min_notional
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a limit price against the orderbook's current price band.
+    pub fn validate_band(
+        &self,
+        price: Decimal,
+        band_limits: Option<(Decimal, Decimal)>,
+    ) -> Result<(), String> {
+        match band_limits {
+            Some((low, high)) if price < low || price > high => Err(format!(
+                "Price {} is outside the price band {} - {}",
+                price,
+                low.round_dp(self.tick_size.scale()),
+                high.round_dp(self.tick_size.scale())
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 24h ticker statistics.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats24h {
+    pub open: Option<Decimal>,
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+    pub close: Option<Decimal>,
+    pub base_volume: Decimal,
+    pub quote_volume: Decimal,
+    pub trade_count: u64,
+}
+
+/// A funding settlement, paid by longs when `rate` is positive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FundingRate {
+    pub market: String,
+    pub rate: Decimal,
+    pub premium_index: Decimal,
+    pub mark_price: Decimal,
+    pub index_price: Decimal,
+    pub timestamp: i64,
+}
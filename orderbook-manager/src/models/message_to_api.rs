@@ -1,6 +1,10 @@
 use crate::services::price_service::PriceInfo;
 
-use super::{Depth, GetQuoteResponse, MarginPositionsPayload, Order, UserBalancesPayload};
+use super::{
+    Depth, Fill, FundingRate, GetQuoteResponse, MarginCallEvent, MarginMode, MarginPosition,
+    MarginPositionsPayload, MarketSpec, MarketStatus, Order, PreventedMatch, Stats24h,
+    TriggerRejectedEvent, UserBalancesPayload,
+};
 use rust_decimal::Decimal;
 use serde::Serialize;
 
@@ -11,6 +15,10 @@ pub enum MessageToApi {
     OrderPlaced { payload: OrderPlacedPayload },
     #[serde(rename = "ORDER_CANCELLED")]
     OrderCancelled { payload: OrderCancelledPayload },
+    #[serde(rename = "ORDER_AMENDED")]
+    OrderAmended { payload: OrderAmendedPayload },
+    #[serde(rename = "ORDERS_CANCELLED")]
+    OrdersCancelled { payload: OrdersCancelledPayload },
     #[serde(rename = "OPEN_ORDERS")]
     OpenOrders { payload: OpenOrdersPayload },
     #[serde(rename = "DEPTH")]
@@ -21,11 +29,24 @@ pub enum MessageToApi {
     UserBalances { payload: UserBalancesPayload },
     #[serde(rename = "GET_MARGIN_POSITIONS")]
     GetMarginPositions { payload: MarginPositionsPayload },
+    #[serde(rename = "MARKET_CREATED")]
+    MarketCreated { payload: MarketSpec },
+    #[serde(rename = "MARKET_STATUS")]
+    MarketStatus { payload: MarketStatusPayload },
+    #[serde(rename = "MARKETS")]
+    Markets { payload: MarketsPayload },
+    #[serde(rename = "ERROR")]
+    Error { payload: ErrorPayload },
     #[serde(rename = "TICKER_PRICE")]
-    TickerPrice {
-        market: String,
-        price: Option<PriceInfo>,
-    },
+    TickerPrice { payload: TickerPayload },
+    #[serde(rename = "FUNDING_HISTORY")]
+    FundingHistory { payload: FundingHistoryPayload },
+    #[serde(rename = "MARGIN_MODE")]
+    MarginMode { payload: MarginModePayload },
+    #[serde(rename = "COLLATERAL_ADJUSTED")]
+    CollateralAdjusted { payload: CollateralAdjustedPayload },
+    #[serde(rename = "ACCOUNT")]
+    Account { payload: AccountPayload },
 }
 
 #[derive(Debug, Serialize)]
@@ -33,6 +54,10 @@ pub struct OrderPlacedPayload {
     pub order_id: String,
     pub remaining_qty: Decimal,
     pub filled_qty: Decimal,
+    pub fee: Decimal,
+    pub fee_asset: String,
+    pub fills: Vec<Fill>,
+    pub prevented_matches: Vec<PreventedMatch>,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,7 +65,90 @@ pub struct OrderCancelledPayload {
     pub message: Option<String>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct OrderAmendedPayload {
+    pub order_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrdersCancelledPayload {
+    pub order_ids: Vec<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct OpenOrdersPayload {
     pub open_orders: Vec<Order>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct MarketsPayload {
+    pub markets: Vec<MarketSpec>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FundingHistoryPayload {
+    pub market: String,
+    pub history: Vec<FundingRate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarginModePayload {
+    pub user_id: String,
+    pub market: String,
+    pub mode: MarginMode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollateralAdjustedPayload {
+    pub position: MarginPosition,
+    pub liquidation_price: Decimal,
+}
+
+/// A balance valued in USDC, `None` without a USDC price.
+#[derive(Debug, Serialize)]
+pub struct BalanceValue {
+    pub ticker: String,
+    pub balance: Decimal,
+    pub locked_balance: Decimal,
+    pub value: Option<Decimal>,
+}
+
+/// A user's account valued in USDC.
+#[derive(Debug, Serialize)]
+pub struct AccountPayload {
+    pub user_id: String,
+    pub balances: Vec<BalanceValue>,
+    pub balance_value: Decimal,
+    pub unrealized_pnl: Decimal,
+    pub realized_pnl: Decimal,
+    pub equity: Decimal,
+    pub position_value: Decimal,
+    pub initial_margin: Decimal,
+    pub maintenance_margin: Decimal,
+    /// USDC free for new margin orders, less losses on cross positions.
+    pub free_collateral: Decimal,
+    pub leverage: Option<Decimal>,
+    pub margin_calls: Vec<MarginCallEvent>,
+    pub rejected_triggers: Vec<TriggerRejectedEvent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorPayload {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarketStatusPayload {
+    pub market: String,
+    pub status: MarketStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TickerPayload {
+    pub market: String,
+    pub price: Option<PriceInfo>,
+    pub stats: Stats24h,
+}
@@ -1,4 +1,5 @@
 mod incoming_message;
+mod market;
 mod message_from_api;
 mod message_to_api;
 mod message_to_db;
@@ -6,6 +7,7 @@ mod order;
 mod user;
 
 pub use incoming_message::*;
+pub use market::*;
 pub use message_from_api::*;
 pub use message_to_api::*;
 pub use message_to_db::*;
@@ -20,14 +22,60 @@ pub enum OrderType {
     MarginLong,
     MarginShort,
     Spot,
+    Market,
+    StopMarket,
+    StopLimit,
+    TakeProfit,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum TimeInForce {
+    #[default]
+    Gtc,
+    Ioc,
+    Fok,
+    PostOnly,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum SelfTradePrevention {
+    CancelNewest,
+    #[default]
+    CancelOldest,
+    CancelBoth,
+    DecrementAndCancel,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PreventedMatch {
+    pub maker_order_id: String,
+    pub quantity: Decimal,
+    pub mode: SelfTradePrevention,
+}
+
+/// A fill between a maker and a taker order.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Fill {
+    pub trade_id: String,
+    pub market: String,
+    pub maker_order_id: String,
+    pub taker_order_id: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub taker_side: OrderSide,
+    pub maker_fee: Decimal,
+    pub maker_fee_asset: String,
+    pub taker_fee: Decimal,
+    pub taker_fee_asset: String,
+    pub timestamp: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Balance {
     pub ticker: String,
@@ -35,14 +83,22 @@ pub struct Balance {
     pub locked_balance: Decimal,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PositionType {
     Long,
     Short,
 }
 
+/// How a user's positions in a market are margined.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum MarginMode {
+    #[default]
+    Isolated,
+    Cross,
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct MarginPosition {
     pub asset: String,
     pub user_id: String,
@@ -52,6 +108,123 @@ pub struct MarginPosition {
     pub leverage: Decimal,
     pub collateral: Decimal,
     pub unrealized_pnl: Decimal,
+    /// Auto-deleveraging queue position, `None` unless in profit.
+    #[serde(default)]
+    pub adl_rank: Option<u32>,
+    /// Liquidation price as of the last PnL tick.
+    #[serde(default)]
+    pub liquidation_price: Option<Decimal>,
+    /// Equity over value at the mark price.
+    #[serde(default)]
+    pub margin_ratio: Option<Decimal>,
+}
+
+impl MarginPosition {
+    pub fn pnl_at(&self, price: Decimal) -> Decimal {
+        match self.position_type {
+            PositionType::Long => (price - self.entry_price) * self.size,
+            PositionType::Short => (self.entry_price - price) * self.size,
+        }
+    }
+
+    /// Collateral plus the unrealized PnL at `mark_price`.
+    pub fn equity_at(&self, mark_price: Decimal) -> Decimal {
+        self.collateral + self.pnl_at(mark_price)
+    }
+
+    pub fn is_liquidatable(&self, mark_price: Decimal, maintenance_margin_rate: Decimal) -> bool {
+        self.equity_at(mark_price) < self.size * mark_price * maintenance_margin_rate
+    }
+
+    /// Mark price at which equity falls to the maintenance margin.
+    pub fn isolated_liquidation_price(&self, maintenance_margin_rate: Decimal) -> Decimal {
+        let collateral_per_unit = self.collateral / self.size;
+        match self.position_type {
+            PositionType::Long => ((self.entry_price - collateral_per_unit)
+                / (Decimal::ONE - maintenance_margin_rate))
+                .max(Decimal::ZERO),
+            PositionType::Short => {
+                (self.entry_price + collateral_per_unit) / (Decimal::ONE + maintenance_margin_rate)
+            }
+        }
+    }
+
+    /// The price at which the position's losses use up its collateral.
+    pub fn bankruptcy_price(&self) -> Decimal {
+        let collateral_per_unit = self.collateral / self.size;
+        match self.position_type {
+            PositionType::Long => (self.entry_price - collateral_per_unit).max(Decimal::ZERO),
+            PositionType::Short => self.entry_price + collateral_per_unit,
+        }
+    }
+
+    /// Auto-deleveraging score at `mark_price`, `None` unless in profit.
+    pub fn adl_score(&self, mark_price: Decimal) -> Option<Decimal> {
+        let pnl = self.pnl_at(mark_price);
+        if pnl <= Decimal::ZERO || self.collateral <= Decimal::ZERO {
+            return None;
+        }
+        let effective_leverage = self.size * mark_price / self.equity_at(mark_price);
+        Some(pnl / self.collateral * effective_leverage)
+    }
+}
+
+/// A position closed by the liquidation engine.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LiquidationEvent {
+    pub market: String,
+    pub user_id: String,
+    pub position_type: PositionType,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub mark_price: Decimal,
+    pub realized_pnl: Decimal,
+    pub fee: Decimal,
+    pub insurance_fund_draw: Decimal,
+    /// Part of `quantity` closed by auto-deleveraging.
+    pub deleveraged_quantity: Decimal,
+    pub timestamp: i64,
+}
+
+/// A profitable position reduced by auto-deleveraging.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdlEvent {
+    pub market: String,
+    pub user_id: String,
+    pub position_type: PositionType,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub realized_pnl: Decimal,
+    pub remaining_size: Decimal,
+    pub timestamp: i64,
+}
+
+/// A warning that maintenance margin reached `threshold` of equity.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MarginCallEvent {
+    pub market: String,
+    pub user_id: String,
+    pub position_type: PositionType,
+    pub threshold: Decimal,
+    pub equity: Decimal,
+    pub maintenance_margin: Decimal,
+    pub mark_price: Decimal,
+    pub liquidation_price: Option<Decimal>,
+    pub timestamp: i64,
+}
+
+/// A trigger order that fired but was rejected when submitted.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TriggerRejectedEvent {
+    pub order_id: String,
+    pub user_id: String,
+    pub market: String,
+    pub order_type: OrderType,
+    pub side: OrderSide,
+    pub quantity: Decimal,
+    pub trigger_price: Option<Decimal>,
+    pub reason: String,
+    pub timestamp: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,6 +237,36 @@ pub struct Order {
     pub is_margin: bool,
     pub leverage: Option<Decimal>,
     pub timestamp: i64,
+    pub order_type: OrderType,
+    pub trigger_price: Option<Decimal>,
+    pub iceberg: Option<Iceberg>,
+    /// Margin still locked for the unfilled quantity of a margin order.
+    #[serde(skip)]
+    pub reserved_margin: Decimal,
+}
+
+impl Order {
+    pub fn visible_quantity(&self) -> Decimal {
+        match &self.iceberg {
+            Some(iceberg) => iceberg.visible_quantity,
+            None => self.quantity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Iceberg {
+    pub display_quantity: Decimal,
+    pub visible_quantity: Decimal,
+}
+
+impl Iceberg {
+    pub fn new(display_quantity: Decimal, quantity: Decimal) -> Self {
+        Iceberg {
+            display_quantity,
+            visible_quantity: display_quantity.min(quantity),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,4 +1,8 @@
-use super::{Balance, MarginPosition};
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    Balance, MarginCallEvent, MarginMode, MarginPosition, PositionType, TriggerRejectedEvent,
+};
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
@@ -12,6 +16,18 @@ pub struct User {
     pub margin_used: Decimal,
     pub max_leverage: Decimal,
     pub realized_pnl: Decimal,
+    /// Traded notional per day (days since the epoch), used for fee tiers.
+    #[serde(default)]
+    pub daily_volume: BTreeMap<i64, Decimal>,
+    /// Margin mode per market, isolated unless set otherwise.
+    #[serde(default)]
+    pub margin_modes: HashMap<String, MarginMode>,
+    /// The most recent margin calls, oldest first.
+    #[serde(default)]
+    pub margin_calls: Vec<MarginCallEvent>,
+    /// The most recent rejected trigger orders, oldest first.
+    #[serde(default)]
+    pub rejected_triggers: Vec<TriggerRejectedEvent>,
 }
 
 impl User {
@@ -24,6 +40,111 @@ impl User {
             margin_used: Decimal::ZERO,
             max_leverage: dec!(10),
             realized_pnl: Decimal::ZERO,
+            daily_volume: BTreeMap::new(),
+            margin_modes: HashMap::new(),
+            margin_calls: Vec::new(),
+            rejected_triggers: Vec::new(),
+        }
+    }
+
+    pub fn margin_mode(&self, market: &str) -> MarginMode {
+        self.margin_modes.get(market).copied().unwrap_or_default()
+    }
+
+    /// Quote balance that is neither locked by orders nor held as collateral.
+    pub fn free_balance(&self, ticker: &str) -> Decimal {
+        self.balances
+            .iter()
+            .find(|b| b.ticker == ticker)
+            .map_or(Decimal::ZERO, |b| b.balance - b.locked_balance)
+    }
+
+    /// Records a margin call, keeping only the last `MARGIN_CALL_HISTORY`.
+    pub fn record_margin_call(&mut self, margin_call: MarginCallEvent) {
+        self.margin_calls.push(margin_call);
+        let excess = self.margin_calls.len().saturating_sub(MARGIN_CALL_HISTORY);
+        self.margin_calls.drain(..excess);
+    }
+
+    /// Records a rejected trigger order, keeping only the most recent.
+    pub fn record_rejected_trigger(&mut self, rejected: TriggerRejectedEvent) {
+        self.rejected_triggers.push(rejected);
+        let excess = self
+            .rejected_triggers
+            .len()
+            .saturating_sub(REJECTED_TRIGGER_HISTORY);
+        self.rejected_triggers.drain(..excess);
+    }
+
+    /// Notional traded over the 30 days up to `now`.
+    pub fn rolling_volume(&self, now: i64) -> Decimal {
+        let today = now / SECONDS_PER_DAY;
+        self.daily_volume
+            .range(today - VOLUME_WINDOW_DAYS + 1..)
+            .map(|(_, volume)| *volume)
+            .sum()
+    }
+
+    pub fn record_volume(&mut self, now: i64, notional: Decimal) {
+        let today = now / SECONDS_PER_DAY;
+        *self.daily_volume.entry(today).or_default() += notional;
+        self.daily_volume
+            .retain(|day, _| *day > today - VOLUME_WINDOW_DAYS);
+    }
+
+    pub fn credit(&mut self, ticker: &str, amount: Decimal) {
+        match self.balances.iter_mut().find(|b| b.ticker == ticker) {
+            Some(balance) => balance.balance += amount,
+            None => self.balances.push(Balance {
+                ticker: ticker.to_string(),
+                balance: amount,
+                locked_balance: Decimal::ZERO,
+            }),
+        }
+    }
+
+    /// Closes up to `quantity` of a position at `price`, returning the realized PnL.
+    pub fn close_position(
+        &mut self,
+        market: &str,
+        position_type: &PositionType,
+        quantity: Decimal,
+        price: Decimal,
+        quote_asset: &str,
+    ) -> Decimal {
+        let Some(index) = self
+            .margin_positions
+            .iter()
+            .position(|p| p.asset == market && &p.position_type == position_type)
+        else {
+            return Decimal::ZERO;
+        };
+
+        let position = &mut self.margin_positions[index];
+        let closed = quantity.min(position.size);
+        let realized_pnl = match position.position_type {
+            PositionType::Long => (price - position.entry_price) * closed,
+            PositionType::Short => (position.entry_price - price) * closed,
+        };
+        let released = position.collateral * closed / position.size;
+
+        position.size -= closed;
+        position.collateral -= released;
+        if position.size.is_zero() {
+            self.margin_positions.remove(index);
         }
+
+        self.realized_pnl += realized_pnl;
+        self.margin_used -= released;
+        if let Some(quote_balance) = self.balances.iter_mut().find(|b| b.ticker == quote_asset) {
+            quote_balance.balance += realized_pnl;
+            quote_balance.locked_balance -= released;
+        }
+        realized_pnl
     }
 }
+
+const SECONDS_PER_DAY: i64 = 86_400;
+const VOLUME_WINDOW_DAYS: i64 = 30;
+const MARGIN_CALL_HISTORY: usize = 50;
+const REJECTED_TRIGGER_HISTORY: usize = 50;
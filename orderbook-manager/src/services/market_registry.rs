@@ -0,0 +1,81 @@
+use std::{fs, path::PathBuf};
+
+use rust_decimal_macros::dec;
+use tracing::{error, info, warn};
+
+use crate::{constants::MARKETS_CONFIG_ENV, models::MarketSpec};
+
+/// Markets listed on the engine, persisted to `MARKETS_CONFIG` when set.
+pub struct MarketRegistry {
+    path: Option<PathBuf>,
+}
+
+impl MarketRegistry {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        MarketRegistry { path }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(std::env::var(MARKETS_CONFIG_ENV).ok().map(PathBuf::from))
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn load(&self) -> Vec<MarketSpec> {
+        let Some(path) = &self.path else {
+            return Self::default_markets();
+        };
+
+        let markets = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                serde_json::from_str::<Vec<MarketSpec>>(&contents).map_err(|e| e.to_string())
+            });
+
+        match markets {
+            Ok(markets) => {
+                info!(?path, count = markets.len(), "Loaded market registry");
+                markets
+            }
+            Err(e) => {
+                warn!(
+                    ?path,
+                    "Failed to load market registry, using defaults: {}", e
+                );
+                Self::default_markets()
+            }
+        }
+    }
+
+    pub fn save(&self, markets: &[MarketSpec]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let result = serde_json::to_string_pretty(markets)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| fs::write(path, contents).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            error!(?path, "Failed to save market registry: {}", e);
+        }
+    }
+
+    pub fn default_markets() -> Vec<MarketSpec> {
+        vec![
+            MarketSpec::new("SOL", "USDC"),
+            MarketSpec {
+                lot_size: dec!(0.0001),
+                min_quantity: dec!(0.0001),
+                ..MarketSpec::new("BTC", "USDC")
+            },
+            MarketSpec {
+                lot_size: dec!(0.001),
+                min_quantity: dec!(0.001),
+                ..MarketSpec::new("ETH", "USDC")
+            },
+        ]
+    }
+}
@@ -1,4 +1,5 @@
 pub mod redis_manager;
 
+pub mod market_registry;
 pub mod pnl_service;
 pub mod price_service;
@@ -1,29 +1,48 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
+use chrono::Utc;
 use rust_decimal::Decimal;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tracing::{error, info};
 
-use crate::models::{MarginPosition, PositionType, User};
+use crate::{
+    models::{
+        AccountPayload, BalanceValue, MarginCallEvent, MarginMode, MarginPosition,
+        MarginPositionsPayload, PositionType, User,
+    },
+    trade::{rank_adl_queue, Orderbook},
+};
 
-use super::price_service::PriceService;
+use super::{price_service::PriceService, redis_manager::RedisManager};
 
 pub struct PnlService {
     users: Arc<RwLock<Vec<User>>>,
     price_service: Arc<PriceService>,
+    orderbooks: Arc<Mutex<HashMap<String, Arc<Mutex<Orderbook>>>>>,
+    /// The highest margin call threshold each open position has crossed.
+    margin_call_levels: Mutex<HashMap<(String, String, PositionType), Decimal>>,
 }
 
 impl PnlService {
-    pub fn new(users: Arc<RwLock<Vec<User>>>, price_service: Arc<PriceService>) -> Self {
+    pub fn new(
+        users: Arc<RwLock<Vec<User>>>,
+        price_service: Arc<PriceService>,
+        orderbooks: Arc<Mutex<HashMap<String, Arc<Mutex<Orderbook>>>>>,
+    ) -> Self {
         PnlService {
             users,
             price_service,
+            orderbooks,
+            margin_call_levels: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn start_monitoring(&self) {
-        let users = self.users.clone();
-        let price_service = self.price_service.clone();
+    pub fn start_monitoring(self: &Arc<Self>) {
+        let service = self.clone();
 
         std::thread::Builder::new()
             .name("pnl_monitor".to_string())
@@ -35,85 +54,420 @@ impl PnlService {
 
                 rt.block_on(async move {
                     let mut interval = tokio::time::interval(Duration::from_secs(1));
+                    let mut published: HashMap<String, Vec<MarginPosition>> = HashMap::new();
                     info!("Started PNL monitoring thread");
 
                     loop {
                         interval.tick().await;
-                        if let Err(e) = Self::check_positions(&users, &price_service).await {
-                            error!("Error checking positions: {}", e);
-                        }
+                        let positions = service.check_positions().await;
+                        Self::publish_position_updates(&mut published, positions);
                     }
                 });
             })
             .expect("Failed to spawn PNL monitoring thread");
     }
 
-    async fn check_positions(
-        users: &Arc<RwLock<Vec<User>>>,
-        price_service: &Arc<PriceService>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut users = users.write().await;
+    /// Publishes each user's positions when they changed since the last tick.
+    fn publish_position_updates(
+        published: &mut HashMap<String, Vec<MarginPosition>>,
+        positions: HashMap<String, Vec<MarginPosition>>,
+    ) {
+        let closed: Vec<String> = published
+            .keys()
+            .filter(|user_id| !positions.contains_key(*user_id))
+            .cloned()
+            .collect();
+        let updates = positions
+            .into_iter()
+            .filter(|(user_id, positions)| published.get(user_id) != Some(positions))
+            .chain(closed.into_iter().map(|user_id| (user_id, Vec::new())));
 
-        for user in users.iter_mut() {
-            let mut positions_to_liquidate = Vec::new();
+        let redis_manager = RedisManager::instance();
+        for (user_id, positions) in updates.collect::<Vec<_>>() {
+            let payload = MarginPositionsPayload {
+                positions: positions.clone(),
+            };
+            if let Err(e) = redis_manager.publish_message(
+                &format!("position@{}", user_id),
+                &serde_json::to_value(&payload).unwrap(),
+            ) {
+                error!(?user_id, "Failed to publish position update: {}", e);
+            }
 
-            for position in user.margin_positions.iter() {
-                let market = format!("{}_USDC", position.asset);
-                if let Some(price) = price_service.get_price(&market).await {
-                    let liquidation_threshold = position.collateral * Decimal::new(-80, 2);
-                    if position.unrealized_pnl <= liquidation_threshold {
-                        positions_to_liquidate.push((position.clone(), price));
-                    }
+            if positions.is_empty() {
+                published.remove(&user_id);
+            } else {
+                published.insert(user_id, positions);
+            }
+        }
+    }
+
+    /// The maintenance margin rate of every listed market.
+    async fn maintenance_rates(&self) -> HashMap<String, Decimal> {
+        let orderbooks = self.orderbooks.lock().await;
+        let mut rates = HashMap::new();
+        for (market, orderbook) in orderbooks.iter() {
+            let rate = orderbook
+                .lock()
+                .await
+                .spec
+                .liquidation
+                .maintenance_margin_rate;
+            rates.insert(market.clone(), rate);
+        }
+        rates
+    }
+
+    /// The margin call thresholds of every listed market.
+    async fn margin_call_thresholds(&self) -> HashMap<String, Vec<Decimal>> {
+        let orderbooks = self.orderbooks.lock().await;
+        let mut thresholds = HashMap::new();
+        for (market, orderbook) in orderbooks.iter() {
+            let market_thresholds = orderbook
+                .lock()
+                .await
+                .spec
+                .liquidation
+                .margin_call_thresholds
+                .clone();
+            thresholds.insert(market.clone(), market_thresholds);
+        }
+        thresholds
+    }
+
+    /// Mark price of every market with an open position.
+    async fn mark_prices(
+        &self,
+        users: &[User],
+        market: Option<(&str, Decimal)>,
+    ) -> HashMap<String, Decimal> {
+        let mut mark_prices: HashMap<String, Decimal> = market
+            .map(|(market, mark_price)| (market.to_string(), mark_price))
+            .into_iter()
+            .collect();
+        for position in users.iter().flat_map(|user| user.margin_positions.iter()) {
+            if !mark_prices.contains_key(&position.asset) {
+                if let Some(price) = self.price_service.get_price(&position.asset).await {
+                    mark_prices.insert(position.asset.clone(), price);
                 }
             }
+        }
+        mark_prices
+    }
 
-            for (position, price) in positions_to_liquidate {
-                Self::liquidate_position(user, &position, price).await?;
+    /// Positions in `market` to liquidate at `mark_price`.
+    pub async fn liquidation_candidates(
+        &self,
+        market: &str,
+        mark_price: Decimal,
+    ) -> Vec<(String, PositionType)> {
+        let maintenance_rates = self.maintenance_rates().await;
+        let users = self.users.read().await;
+        let mark_prices = self.mark_prices(&users, Some((market, mark_price))).await;
+
+        let quote_asset = market.split('_').nth(1).unwrap_or("USDC");
+        let maintenance_rate = maintenance_rates.get(market).copied().unwrap_or_default();
+
+        let mut candidates = Vec::new();
+        for user in users.iter() {
+            for position in user.margin_positions.iter().filter(|p| p.asset == market) {
+                let liquidatable = match user.margin_mode(market) {
+                    MarginMode::Isolated => position.is_liquidatable(mark_price, maintenance_rate),
+                    MarginMode::Cross => {
+                        let (equity, maintenance_margin, _) = Self::cross_account(
+                            user,
+                            quote_asset,
+                            &mark_prices,
+                            &maintenance_rates,
+                        );
+                        equity < maintenance_margin
+                    }
+                };
+                if liquidatable {
+                    candidates.push((user.id.clone(), position.position_type.clone()));
+                }
             }
         }
-        Ok(())
+        candidates
     }
 
-    async fn liquidate_position(
-        user: &mut User,
+    /// Equity, maintenance margin and value of a position.
+    fn position_margin(
         position: &MarginPosition,
-        price: Decimal,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        info!(
-            user_id = ?user.id,
-            asset = ?position.asset,
-            size = ?position.size,
-            price = ?price,
-            "Liquidating position"
-        );
-
-        let realized_pnl = match position.position_type {
-            PositionType::Long => (price - position.entry_price) * position.size,
-            PositionType::Short => (position.entry_price - price) * position.size,
+        mark_prices: &HashMap<String, Decimal>,
+        maintenance_rates: &HashMap<String, Decimal>,
+    ) -> (Decimal, Decimal, Decimal) {
+        let rate = maintenance_rates
+            .get(&position.asset)
+            .copied()
+            .unwrap_or_default();
+        let (equity, price) = match mark_prices.get(&position.asset) {
+            Some(mark_price) => (position.equity_at(*mark_price), *mark_price),
+            None => (
+                position.collateral + position.unrealized_pnl,
+                position.entry_price,
+            ),
         };
+        (equity, position.size * price * rate, position.size * price)
+    }
+
+    /// Equity, maintenance margin and value of a cross account.
+    fn cross_account(
+        user: &User,
+        quote_asset: &str,
+        mark_prices: &HashMap<String, Decimal>,
+        maintenance_rates: &HashMap<String, Decimal>,
+    ) -> (Decimal, Decimal, Decimal) {
+        user.margin_positions
+            .iter()
+            .filter(|p| {
+                p.asset.split('_').nth(1) == Some(quote_asset)
+                    && user.margin_mode(&p.asset) == MarginMode::Cross
+            })
+            .map(|p| Self::position_margin(p, mark_prices, maintenance_rates))
+            .fold(
+                (user.free_balance(quote_asset), Decimal::ZERO, Decimal::ZERO),
+                |(equity, maintenance, value), (p_equity, p_maintenance, p_value)| {
+                    (
+                        equity + p_equity,
+                        maintenance + p_maintenance,
+                        value + p_value,
+                    )
+                },
+            )
+    }
+
+    /// Liquidation price and margin ratio of a position.
+    fn position_risk(
+        user: &User,
+        position: &MarginPosition,
+        mark_prices: &HashMap<String, Decimal>,
+        maintenance_rates: &HashMap<String, Decimal>,
+    ) -> (Decimal, Option<Decimal>) {
+        let rate = maintenance_rates
+            .get(&position.asset)
+            .copied()
+            .unwrap_or_default();
+        match user.margin_mode(&position.asset) {
+            MarginMode::Isolated => {
+                let margin_ratio = mark_prices.get(&position.asset).map(|mark_price| {
+                    position.equity_at(*mark_price) / (position.size * mark_price)
+                });
+                (position.isolated_liquidation_price(rate), margin_ratio)
+            }
+            MarginMode::Cross => {
+                let quote_asset = position.asset.split('_').nth(1).unwrap_or("USDC");
+                let (equity, maintenance_margin, value) =
+                    Self::cross_account(user, quote_asset, mark_prices, maintenance_rates);
+                let (own_equity, own_maintenance, _) =
+                    Self::position_margin(position, mark_prices, maintenance_rates);
+                let backed = MarginPosition {
+                    collateral: position.collateral + (equity - maintenance_margin)
+                        - (own_equity - own_maintenance),
+                    ..position.clone()
+                };
+                (
+                    backed.isolated_liquidation_price(rate),
+                    Some(equity / value),
+                )
+            }
+        }
+    }
+
+    /// Raises a margin call once per threshold crossed.
+    fn margin_calls(
+        users: &mut [User],
+        levels: &mut HashMap<(String, String, PositionType), Decimal>,
+        mark_prices: &HashMap<String, Decimal>,
+        maintenance_rates: &HashMap<String, Decimal>,
+        thresholds: &HashMap<String, Vec<Decimal>>,
+    ) -> Vec<MarginCallEvent> {
+        let mut margin_calls = Vec::new();
+        let mut open = HashSet::new();
+
+        for user in users.iter_mut() {
+            let mut user_calls = Vec::new();
+            for position in user.margin_positions.iter() {
+                let key = (
+                    user.id.clone(),
+                    position.asset.clone(),
+                    position.position_type.clone(),
+                );
+                open.insert(key.clone());
+                let Some(mark_price) = mark_prices.get(&position.asset) else {
+                    continue;
+                };
+
+                let (equity, maintenance_margin, _) = match user.margin_mode(&position.asset) {
+                    MarginMode::Isolated => {
+                        Self::position_margin(position, mark_prices, maintenance_rates)
+                    }
+                    MarginMode::Cross => {
+                        let quote_asset = position.asset.split('_').nth(1).unwrap_or("USDC");
+                        Self::cross_account(user, quote_asset, mark_prices, maintenance_rates)
+                    }
+                };
+                let level = thresholds
+                    .get(&position.asset)
+                    .into_iter()
+                    .flatten()
+                    .filter(|threshold| maintenance_margin >= **threshold * equity)
+                    .max()
+                    .copied();
+                let previous = match level {
+                    Some(level) => levels.insert(key, level),
+                    None => levels.remove(&key),
+                };
 
-        user.realized_pnl += realized_pnl;
+                if let Some(threshold) = level.filter(|_| level > previous) {
+                    user_calls.push(MarginCallEvent {
+                        market: position.asset.clone(),
+                        user_id: user.id.clone(),
+                        position_type: position.position_type.clone(),
+                        threshold,
+                        equity,
+                        maintenance_margin,
+                        mark_price: *mark_price,
+                        liquidation_price: position.liquidation_price,
+                        timestamp: Utc::now().timestamp(),
+                    });
+                }
+            }
 
-        if let Some(usdc_balance) = user.balances.iter_mut().find(|b| b.ticker == "USDC") {
-            usdc_balance.balance += realized_pnl + position.collateral;
-            usdc_balance.locked_balance -= position.collateral;
+            for margin_call in user_calls {
+                user.record_margin_call(margin_call.clone());
+                margin_calls.push(margin_call);
+            }
         }
 
-        if position.position_type == PositionType::Short {
-            if let Some(asset_balance) = user
-                .balances
-                .iter_mut()
-                .find(|b| b.ticker == position.asset)
+        levels.retain(|key, _| open.contains(key));
+        margin_calls
+    }
+
+    /// Marks every margin position to market and publishes margin calls.
+    pub async fn check_positions(&self) -> HashMap<String, Vec<MarginPosition>> {
+        let maintenance_rates = self.maintenance_rates().await;
+        let thresholds = self.margin_call_thresholds().await;
+        let mut users = self.users.write().await;
+        let mark_prices = self.mark_prices(&users, None).await;
+
+        for user in users.iter_mut() {
+            for position in user.margin_positions.iter_mut() {
+                if let Some(mark_price) = mark_prices.get(&position.asset) {
+                    position.unrealized_pnl = position.pnl_at(*mark_price);
+                }
+            }
+
+            let risks: Vec<(Decimal, Option<Decimal>)> = user
+                .margin_positions
+                .iter()
+                .map(|p| Self::position_risk(user, p, &mark_prices, &maintenance_rates))
+                .collect();
+            for (position, (liquidation_price, margin_ratio)) in
+                user.margin_positions.iter_mut().zip(risks)
             {
-                asset_balance.locked_balance -= position.size;
+                position.liquidation_price = Some(liquidation_price);
+                position.margin_ratio = margin_ratio;
             }
         }
+        for (market, mark_price) in mark_prices.iter() {
+            rank_adl_queue(&mut users, market, *mark_price);
+        }
 
-        user.margin_used -= position.collateral;
+        let margin_calls = Self::margin_calls(
+            &mut users,
+            &mut *self.margin_call_levels.lock().await,
+            &mark_prices,
+            &maintenance_rates,
+            &thresholds,
+        );
+        let redis_manager = RedisManager::instance();
+        for margin_call in margin_calls {
+            info!(
+                user_id = ?margin_call.user_id,
+                market = ?margin_call.market,
+                "Margin call at {} of equity",
+                margin_call.threshold
+            );
+            if let Err(e) = redis_manager.publish_message(
+                &format!("margin_call@{}", margin_call.user_id),
+                &serde_json::to_value(&margin_call).unwrap(),
+            ) {
+                error!(
+                    user_id = ?margin_call.user_id,
+                    "Failed to publish margin call: {}", e
+                );
+            }
+        }
 
-        user.margin_positions
-            .retain(|p| p.asset != position.asset || p.position_type != position.position_type);
+        users
+            .iter()
+            .filter(|user| !user.margin_positions.is_empty())
+            .map(|user| (user.id.clone(), user.margin_positions.clone()))
+            .collect()
+    }
+
+    /// Values the user's account in USDC at the current mark prices.
+    pub async fn account_summary(&self, user_id: &str) -> Option<AccountPayload> {
+        let maintenance_rates = self.maintenance_rates().await;
+        let users = self.users.read().await;
+        let user = users.iter().find(|u| u.id == user_id)?;
+        let mark_prices = self.mark_prices(std::slice::from_ref(user), None).await;
+
+        let mut balances = Vec::new();
+        for balance in user.balances.iter() {
+            let price = if balance.ticker == VALUATION_ASSET {
+                Some(Decimal::ONE)
+            } else {
+                self.price_service
+                    .get_price(&format!("{}_{}", balance.ticker, VALUATION_ASSET))
+                    .await
+            };
+            balances.push(BalanceValue {
+                ticker: balance.ticker.clone(),
+                balance: balance.balance,
+                locked_balance: balance.locked_balance,
+                value: price.map(|price| balance.balance * price),
+            });
+        }
+        let balance_value: Decimal = balances.iter().filter_map(|b| b.value).sum();
 
-        Ok(())
+        let mut unrealized_pnl = Decimal::ZERO;
+        let mut cross_pnl = Decimal::ZERO;
+        let mut position_value = Decimal::ZERO;
+        let mut initial_margin = Decimal::ZERO;
+        let mut maintenance_margin = Decimal::ZERO;
+        for position in user.margin_positions.iter() {
+            let (equity, maintenance, value) =
+                Self::position_margin(position, &mark_prices, &maintenance_rates);
+            let pnl = equity - position.collateral;
+            unrealized_pnl += pnl;
+            if user.margin_mode(&position.asset) == MarginMode::Cross {
+                cross_pnl += pnl;
+            }
+            position_value += value;
+            initial_margin += value / position.leverage;
+            maintenance_margin += maintenance;
+        }
+
+        let equity = balance_value + unrealized_pnl;
+        Some(AccountPayload {
+            user_id: user.id.clone(),
+            balances,
+            balance_value,
+            unrealized_pnl,
+            realized_pnl: user.realized_pnl,
+            equity,
+            position_value,
+            initial_margin,
+            maintenance_margin,
+            free_collateral: user.free_balance(VALUATION_ASSET) + cross_pnl.min(Decimal::ZERO),
+            leverage: (equity > Decimal::ZERO).then(|| position_value / equity),
+            margin_calls: user.margin_calls.clone(),
+            rejected_triggers: user.rejected_triggers.clone(),
+        })
     }
 }
+
+/// The asset account values are expressed in.
+const VALUATION_ASSET: &str = "USDC";
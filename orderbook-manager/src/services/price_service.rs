@@ -1,7 +1,19 @@
+use redis::Commands;
 use rust_decimal::Decimal;
 use serde::Serialize;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::constants::INDEX_SOURCES_ENV;
+
+use super::redis_manager::RedisManager;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize)]
@@ -12,15 +24,133 @@ pub struct PriceInfo {
     pub timestamp: i64,
 }
 
+/// An external reference price for a market.
+pub trait IndexPriceSource: Send + Sync {
+    fn name(&self) -> &str;
+    fn fetch(&self, market: &str) -> Option<Decimal>;
+}
+
+/// Reads prices from a JSON file of market to price, re-read on every fetch.
+pub struct FileIndexSource {
+    path: PathBuf,
+}
+
+impl FileIndexSource {
+    pub fn new(path: PathBuf) -> Self {
+        FileIndexSource { path }
+    }
+}
+
+impl IndexPriceSource for FileIndexSource {
+    fn name(&self) -> &str {
+        self.path.to_str().unwrap_or("file")
+    }
+
+    fn fetch(&self, market: &str) -> Option<Decimal> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let prices: HashMap<String, Decimal> = serde_json::from_str(&contents).ok()?;
+        prices.get(market).copied()
+    }
+}
+
+/// Reads prices published to Redis under `index:{name}:{market}`.
+pub struct RedisIndexSource {
+    name: String,
+    connection: Mutex<Option<redis::Connection>>,
+}
+
+impl RedisIndexSource {
+    pub fn new(name: String) -> Self {
+        RedisIndexSource {
+            name,
+            connection: Mutex::new(None),
+        }
+    }
+}
+
+impl IndexPriceSource for RedisIndexSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn fetch(&self, market: &str) -> Option<Decimal> {
+        let mut connection = self.connection.lock().ok()?;
+        if connection.is_none() {
+            *connection = RedisManager::instance().get_connection().ok();
+        }
+        let price: Option<String> = match connection
+            .as_mut()?
+            .get(format!("index:{}:{}", self.name, market))
+        {
+            Ok(price) => price,
+            Err(e) => {
+                warn!(source = ?self.name, error = ?e, "Index price fetch failed, reconnecting");
+                *connection = None;
+                return None;
+            }
+        };
+        Decimal::from_str(&price?).ok()
+    }
+}
+
 #[allow(dead_code)]
 pub struct PriceService {
     prices: Arc<RwLock<HashMap<String, PriceInfo>>>,
+    index_sources: Vec<Box<dyn IndexPriceSource>>,
 }
 
 impl PriceService {
     pub fn new() -> Self {
+        Self::with_index_sources(Self::index_sources_from_env())
+    }
+
+    pub fn with_index_sources(index_sources: Vec<Box<dyn IndexPriceSource>>) -> Self {
         PriceService {
             prices: Arc::new(RwLock::new(HashMap::new())),
+            index_sources,
+        }
+    }
+
+    /// Parses `INDEX_SOURCES`, e.g. `file:<path>,redis:<name>`.
+    fn index_sources_from_env() -> Vec<Box<dyn IndexPriceSource>> {
+        let Ok(config) = std::env::var(INDEX_SOURCES_ENV) else {
+            return Vec::new();
+        };
+
+        let mut sources: Vec<Box<dyn IndexPriceSource>> = Vec::new();
+        for entry in config.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once(':') {
+                Some(("file", path)) => {
+                    sources.push(Box::new(FileIndexSource::new(PathBuf::from(path))))
+                }
+                Some(("redis", name)) => {
+                    sources.push(Box::new(RedisIndexSource::new(name.to_string())))
+                }
+                _ => warn!(?entry, "Ignoring unknown index price source"),
+            }
+        }
+        let names: Vec<&str> = sources.iter().map(|source| source.name()).collect();
+        info!(?names, "Configured index price sources");
+        sources
+    }
+
+    /// The median price across the sources that have one for `market`.
+    pub fn index_price(&self, market: &str) -> Option<Decimal> {
+        let mut prices: Vec<Decimal> = self
+            .index_sources
+            .iter()
+            .filter_map(|source| source.fetch(market))
+            .collect();
+        if prices.is_empty() {
+            return None;
+        }
+
+        prices.sort();
+        let mid = prices.len() / 2;
+        if prices.len().is_multiple_of(2) {
+            Some((prices[mid - 1] + prices[mid]) / Decimal::TWO)
+        } else {
+            Some(prices[mid])
         }
     }
 
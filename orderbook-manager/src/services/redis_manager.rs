@@ -3,7 +3,10 @@ use lazy_static::lazy_static;
 use redis::{Client, Commands, Connection, RedisResult};
 use serde_json::Value;
 
-use crate::models::{AddTradePayload, MessageToApi};
+use crate::{
+    constants::MESSAGE_FROM_API_CHANNEL,
+    models::{AddTradePayload, MessageToApi},
+};
 
 lazy_static! {
     static ref REDIS_MANAGER: RedisManager = RedisManager::new();
@@ -38,6 +41,11 @@ impl RedisManager {
         conn.publish(channel, message.to_string())
     }
 
+    pub fn push_to_engine(&self, message: &Value) -> RedisResult<()> {
+        let mut conn = self.get_connection()?;
+        conn.lpush(MESSAGE_FROM_API_CHANNEL, message.to_string())
+    }
+
     pub fn push_message_to_db(&self, message: &AddTradePayload) -> RedisResult<()> {
         let mut conn = self.get_connection()?;
         conn.lpush("db_processor", serde_json::to_string(message).unwrap())
@@ -1,30 +1,60 @@
 #[cfg(test)]
 mod orderbook_tests {
     use crate::{
+        constants::{EXCHANGE_FEE_ACCOUNT, INSURANCE_FUND_ACCOUNT},
         models::{
-            Balance, CancelOrderPayload, CreateOrderPayload, MessageFromApi, OrderSide, OrderType,
-            PositionType, User,
+            AdjustCollateralPayload, AmendOrderPayload, Balance, CancelAllOrdersPayload,
+            CancelOrderPayload, ClosePositionPayload, CreateMarketPayload, CreateOrderPayload,
+            FundingConfig, MarginMode, MarginPosition, MarketSpec, MarketStatus, MessageFromApi,
+            Order, OrderSide, OrderType, PositionType, ProductType, SelfTradePrevention,
+            SetMarginModePayload, SetMarketStatusPayload, TimeInForce, User,
         },
-        services::price_service::PriceInfo,
-        trade::Engine,
+        services::{
+            market_registry::MarketRegistry,
+            price_service::{FileIndexSource, IndexPriceSource, PriceInfo, PriceService},
+        },
+        trade::{rank_adl_queue, Engine, Funding, Orderbook, TradeHistory},
     };
+    use chrono::Utc;
+    use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
-    use std::time::Duration;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::Arc,
+        time::Duration,
+    };
+    use uuid::Uuid;
+
+    /// A GTC order in SOL_USDC, for tests to override what they exercise.
+    fn order(
+        user_id: &str,
+        side: OrderSide,
+        order_type: OrderType,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> CreateOrderPayload {
+        CreateOrderPayload {
+            user_id: user_id.to_string(),
+            market: "SOL_USDC".to_string(),
+            price,
+            quantity,
+            side,
+            order_type,
+            is_margin: false,
+            leverage: None,
+            time_in_force: TimeInForce::Gtc,
+            trigger_price: None,
+            display_quantity: None,
+            self_trade_prevention: None,
+            reduce_only: false,
+        }
+    }
 
     #[tokio::test]
     async fn test_create_spot_buy_order() {
         let mut engine = Engine::new();
 
-        let order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(2),
-            side: OrderSide::Buy,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let order = order("1", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(2));
 
         let message = MessageFromApi::CreateOrder { data: order };
         engine.process("test_client".to_string(), message).await;
@@ -33,25 +63,16 @@ mod orderbook_tests {
         let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
 
         assert_eq!(orderbook.bids.len(), 1);
-        assert_eq!(orderbook.bids[0].price, dec!(20));
-        assert_eq!(orderbook.bids[0].quantity, dec!(2));
-        assert_eq!(orderbook.bids[0].user_id, "1");
+        assert_eq!(orderbook.bids.best().unwrap().price, dec!(20));
+        assert_eq!(orderbook.bids.best().unwrap().quantity, dec!(2));
+        assert_eq!(orderbook.bids.best().unwrap().user_id, "1");
     }
 
     #[tokio::test]
     async fn test_create_spot_sell_order() {
         let mut engine = Engine::new();
 
-        let order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(22),
-            quantity: dec!(1),
-            side: OrderSide::Sell,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let order = order("1", OrderSide::Sell, OrderType::Spot, dec!(22), dec!(1));
 
         let message = MessageFromApi::CreateOrder { data: order };
         engine.process("test_client".to_string(), message).await;
@@ -60,25 +81,16 @@ mod orderbook_tests {
         let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
 
         assert_eq!(orderbook.asks.len(), 1);
-        assert_eq!(orderbook.asks[0].price, dec!(22));
-        assert_eq!(orderbook.asks[0].quantity, dec!(1));
-        assert_eq!(orderbook.asks[0].user_id, "1");
+        assert_eq!(orderbook.asks.best().unwrap().price, dec!(22));
+        assert_eq!(orderbook.asks.best().unwrap().quantity, dec!(1));
+        assert_eq!(orderbook.asks.best().unwrap().user_id, "1");
     }
 
     #[tokio::test]
     async fn test_cancel_spot_order() {
         let mut engine = Engine::new();
 
-        let create_order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(2),
-            side: OrderSide::Buy,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let create_order = order("1", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(2));
 
         let message = MessageFromApi::CreateOrder { data: create_order };
         engine.process("test_client".to_string(), message).await;
@@ -86,7 +98,7 @@ mod orderbook_tests {
         let order_id = {
             let orderbooks = engine.orderbooks.lock().await;
             let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
-            orderbook.bids[0].id.clone()
+            orderbook.bids.best().unwrap().id.clone()
         };
 
         let cancel_order = CancelOrderPayload {
@@ -107,30 +119,12 @@ mod orderbook_tests {
     async fn test_order_matching() {
         let mut engine = Engine::new();
 
-        let sell_order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(2),
-            side: OrderSide::Sell,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let sell_order = order("1", OrderSide::Sell, OrderType::Spot, dec!(20), dec!(2));
 
         let message = MessageFromApi::CreateOrder { data: sell_order };
         engine.process("test_client".to_string(), message).await;
 
-        let buy_order = CreateOrderPayload {
-            user_id: "2".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(1),
-            side: OrderSide::Buy,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let buy_order = order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(1));
 
         let message = MessageFromApi::CreateOrder { data: buy_order };
         engine.process("test_client".to_string(), message).await;
@@ -139,7 +133,7 @@ mod orderbook_tests {
         let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
 
         assert_eq!(orderbook.asks.len(), 1);
-        assert_eq!(orderbook.asks[0].quantity, dec!(1));
+        assert_eq!(orderbook.asks.best().unwrap().quantity, dec!(1));
         assert_eq!(orderbook.bids.len(), 0);
     }
 
@@ -147,16 +141,13 @@ mod orderbook_tests {
     async fn test_insufficient_balance() {
         let mut engine = Engine::new();
 
-        let buy_order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20000),
-            quantity: dec!(1000),
-            side: OrderSide::Buy,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let buy_order = order(
+            "1",
+            OrderSide::Buy,
+            OrderType::Spot,
+            dec!(20000),
+            dec!(1000),
+        );
 
         let message = MessageFromApi::CreateOrder { data: buy_order };
         engine.process("test_client".to_string(), message).await;
@@ -192,6 +183,10 @@ mod orderbook_tests {
                 max_leverage: dec!(10),
                 margin_used: dec!(0),
                 realized_pnl: dec!(0),
+                daily_volume: BTreeMap::new(),
+                margin_modes: HashMap::new(),
+                margin_calls: Vec::new(),
+                rejected_triggers: Vec::new(),
             });
 
             // Add counter-party user
@@ -214,34 +209,30 @@ mod orderbook_tests {
                 max_leverage: dec!(10),
                 margin_used: dec!(0),
                 realized_pnl: dec!(0),
+                daily_volume: BTreeMap::new(),
+                margin_modes: HashMap::new(),
+                margin_calls: Vec::new(),
+                rejected_triggers: Vec::new(),
             });
         }
 
         // Create a matching sell order first
-        let sell_order = CreateOrderPayload {
-            user_id: "2".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(5),
-            side: OrderSide::Sell,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let sell_order = order("2", OrderSide::Sell, OrderType::Spot, dec!(20), dec!(5));
 
         let message = MessageFromApi::CreateOrder { data: sell_order };
         engine.process("test_client".to_string(), message).await;
 
         // Then create the margin long order
         let buy_order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(5),
-            side: OrderSide::Buy,
             is_margin: true,
-            order_type: OrderType::MarginLong,
             leverage: Some(dec!(5)),
+            ..order(
+                "1",
+                OrderSide::Buy,
+                OrderType::MarginLong,
+                dec!(20),
+                dec!(5),
+            )
         };
 
         let message = MessageFromApi::CreateOrder { data: buy_order };
@@ -287,6 +278,10 @@ mod orderbook_tests {
                 max_leverage: dec!(10),
                 margin_used: dec!(0),
                 realized_pnl: dec!(0),
+                daily_volume: BTreeMap::new(),
+                margin_modes: HashMap::new(),
+                margin_calls: Vec::new(),
+                rejected_triggers: Vec::new(),
             });
 
             // Add counter-party user
@@ -309,34 +304,30 @@ mod orderbook_tests {
                 max_leverage: dec!(10),
                 margin_used: dec!(0),
                 realized_pnl: dec!(0),
+                daily_volume: BTreeMap::new(),
+                margin_modes: HashMap::new(),
+                margin_calls: Vec::new(),
+                rejected_triggers: Vec::new(),
             });
         }
 
         // Create a matching buy order first
-        let buy_order = CreateOrderPayload {
-            user_id: "2".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(5),
-            side: OrderSide::Buy,
-            is_margin: false,
-            order_type: OrderType::Spot,
-            leverage: Some(dec!(1)),
-        };
+        let buy_order = order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(5));
 
         let message = MessageFromApi::CreateOrder { data: buy_order };
         engine.process("test_client".to_string(), message).await;
 
         // Then create the margin short order
         let short_order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(20),
-            quantity: dec!(5),
-            side: OrderSide::Sell,
             is_margin: true,
-            order_type: OrderType::MarginShort,
             leverage: Some(dec!(5)),
+            ..order(
+                "1",
+                OrderSide::Sell,
+                OrderType::MarginShort,
+                dec!(20),
+                dec!(5),
+            )
         };
 
         let message = MessageFromApi::CreateOrder { data: short_order };
@@ -382,18 +373,23 @@ mod orderbook_tests {
                 max_leverage: dec!(10),
                 margin_used: dec!(0),
                 realized_pnl: dec!(0),
+                daily_volume: BTreeMap::new(),
+                margin_modes: HashMap::new(),
+                margin_calls: Vec::new(),
+                rejected_triggers: Vec::new(),
             });
         }
 
         let create_order = CreateOrderPayload {
-            user_id: "1".to_string(),
-            market: "SOL_USDC".to_string(),
-            price: dec!(100),
-            quantity: dec!(1),
-            side: OrderSide::Buy,
             is_margin: true,
-            order_type: OrderType::MarginLong,
             leverage: Some(dec!(5)),
+            ..order(
+                "1",
+                OrderSide::Buy,
+                OrderType::MarginLong,
+                dec!(100),
+                dec!(1),
+            )
         };
 
         let message = MessageFromApi::CreateOrder { data: create_order };
@@ -422,4 +418,2510 @@ mod orderbook_tests {
             "Position should be liquidated"
         );
     }
+
+    #[tokio::test]
+    async fn test_market_order_sweeps_book() {
+        let mut engine = Engine::new();
+
+        for price in [dec!(20), dec!(21)] {
+            let sell_order = order("1", OrderSide::Sell, OrderType::Spot, price, dec!(1));
+
+            let message = MessageFromApi::CreateOrder { data: sell_order };
+            engine.process("test_client".to_string(), message).await;
+        }
+
+        let market_order = order("2", OrderSide::Buy, OrderType::Market, dec!(0), dec!(3));
+
+        let message = MessageFromApi::CreateOrder { data: market_order };
+        engine.process("test_client".to_string(), message).await;
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+
+            assert_eq!(orderbook.asks.len(), 0);
+            assert_eq!(orderbook.bids.len(), 0, "Remainder should not rest");
+        }
+
+        let users = engine.users.read().await;
+        let buyer = users.iter().find(|u| u.id == "2").unwrap();
+        let usdc = buyer.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        let sol = buyer.balances.iter().find(|b| b.ticker == "SOL").unwrap();
+
+        assert_eq!(usdc.balance, dec!(9959));
+        assert_eq!(usdc.locked_balance, dec!(0));
+        // Two SOL bought, less the 0.2% taker fee
+        assert_eq!(sol.balance, dec!(101.996));
+    }
+
+    #[tokio::test]
+    async fn test_market_order_without_liquidity() {
+        let mut engine = Engine::new();
+
+        let market_order = order("1", OrderSide::Sell, OrderType::Market, dec!(0), dec!(1));
+
+        let err = engine.create_order(&market_order).await.unwrap_err();
+        assert_eq!(err.to_string(), "No liquidity for market order");
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.asks.len(), 0);
+
+        let users = engine.users.read().await;
+        let seller = users.iter().find(|u| u.id == "1").unwrap();
+        let sol = seller.balances.iter().find(|b| b.ticker == "SOL").unwrap();
+        assert_eq!(sol.locked_balance, dec!(0));
+    }
+
+    #[tokio::test]
+    async fn test_ioc_order_remainder_cancelled() {
+        let mut engine = Engine::new();
+
+        let sell_order = order("1", OrderSide::Sell, OrderType::Spot, dec!(20), dec!(1));
+
+        let message = MessageFromApi::CreateOrder { data: sell_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let buy_order = CreateOrderPayload {
+            time_in_force: TimeInForce::Ioc,
+            ..order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(3))
+        };
+
+        let message = MessageFromApi::CreateOrder { data: buy_order };
+        engine.process("test_client".to_string(), message).await;
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.asks.len(), 0);
+            assert_eq!(orderbook.bids.len(), 0, "IOC remainder should not rest");
+        }
+
+        let users = engine.users.read().await;
+        let buyer = users.iter().find(|u| u.id == "2").unwrap();
+        let usdc = buyer.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.balance, dec!(9980));
+        assert_eq!(usdc.locked_balance, dec!(0));
+    }
+
+    #[tokio::test]
+    async fn test_margin_ioc_remainder_releases_margin() {
+        let mut engine = Engine::new();
+
+        let short_order = CreateOrderPayload {
+            is_margin: true,
+            leverage: Some(dec!(5)),
+            ..order(
+                "2",
+                OrderSide::Sell,
+                OrderType::MarginShort,
+                dec!(20),
+                dec!(1),
+            )
+        };
+        engine.create_order(&short_order).await.unwrap();
+
+        // 5 * 20 / 5 = 20 is locked, only the margin of the filled 1 stays
+        let long_order = CreateOrderPayload {
+            is_margin: true,
+            leverage: Some(dec!(5)),
+            time_in_force: TimeInForce::Ioc,
+            ..order(
+                "1",
+                OrderSide::Buy,
+                OrderType::MarginLong,
+                dec!(20),
+                dec!(5),
+            )
+        };
+        let placed = engine.create_order(&long_order).await.unwrap();
+        assert_eq!(placed.filled_qty, dec!(1));
+        assert_eq!(placed.remaining_qty, dec!(4));
+
+        let users = engine.users.read().await;
+        let user = &users[0];
+        assert_eq!(user.margin_positions[0].collateral, dec!(4));
+        let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.locked_balance, dec!(4));
+    }
+
+    #[tokio::test]
+    async fn test_margin_market_order_against_thin_book() {
+        let mut engine = Engine::new();
+
+        let sell_order = order("2", OrderSide::Sell, OrderType::Spot, dec!(20), dec!(2));
+        engine.create_order(&sell_order).await.unwrap();
+
+        // 5 * 20 / 5 = 20 is reserved, only the margin of the filled 2 stays
+        let buy_order = CreateOrderPayload {
+            is_margin: true,
+            leverage: Some(dec!(5)),
+            ..order("1", OrderSide::Buy, OrderType::Market, dec!(0), dec!(5))
+        };
+        let placed = engine.create_order(&buy_order).await.unwrap();
+        assert_eq!(placed.filled_qty, dec!(2));
+
+        let (_, usdc_locked) = locked_balances(&engine, "1").await;
+        assert_eq!(usdc_locked, dec!(8));
+
+        let sell_order = CreateOrderPayload {
+            is_margin: true,
+            leverage: Some(dec!(5)),
+            ..order("1", OrderSide::Sell, OrderType::Market, dec!(0), dec!(5))
+        };
+        let err = engine.create_order(&sell_order).await.unwrap_err();
+        assert!(err.to_string().contains("No liquidity"));
+    }
+
+    #[tokio::test]
+    async fn test_fok_order_rejected_when_not_fully_fillable() {
+        let mut engine = Engine::new();
+
+        let sell_order = order("1", OrderSide::Sell, OrderType::Spot, dec!(20), dec!(1));
+
+        let message = MessageFromApi::CreateOrder { data: sell_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let buy_order = CreateOrderPayload {
+            time_in_force: TimeInForce::Fok,
+            ..order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(2))
+        };
+
+        let message = MessageFromApi::CreateOrder { data: buy_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.asks.len(), 1);
+        assert_eq!(
+            orderbook.asks.best().unwrap().quantity,
+            dec!(1),
+            "Nothing should fill"
+        );
+        assert_eq!(orderbook.bids.len(), 0);
+
+        let users = engine.users.read().await;
+        let buyer = users.iter().find(|u| u.id == "2").unwrap();
+        let usdc = buyer.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.locked_balance, dec!(0));
+    }
+
+    #[tokio::test]
+    async fn test_post_only_order_rejected_when_crossing() {
+        let mut engine = Engine::new();
+
+        let sell_order = order("1", OrderSide::Sell, OrderType::Spot, dec!(20), dec!(1));
+
+        let message = MessageFromApi::CreateOrder { data: sell_order };
+        engine.process("test_client".to_string(), message).await;
+
+        for price in [dec!(21), dec!(19)] {
+            let buy_order = CreateOrderPayload {
+                time_in_force: TimeInForce::PostOnly,
+                ..order("2", OrderSide::Buy, OrderType::Spot, price, dec!(1))
+            };
+
+            let message = MessageFromApi::CreateOrder { data: buy_order };
+            engine.process("test_client".to_string(), message).await;
+        }
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.asks.len(), 1);
+        assert_eq!(orderbook.bids.len(), 1);
+        assert_eq!(orderbook.bids.best().unwrap().price, dec!(19));
+    }
+
+    #[tokio::test]
+    async fn test_stop_market_order_triggers() {
+        let mut engine = Engine::new();
+
+        let buy_order = order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(1));
+
+        let message = MessageFromApi::CreateOrder { data: buy_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let stop_order = CreateOrderPayload {
+            trigger_price: Some(dec!(25)),
+            ..order(
+                "1",
+                OrderSide::Sell,
+                OrderType::StopMarket,
+                dec!(0),
+                dec!(1),
+            )
+        };
+
+        let message = MessageFromApi::CreateOrder { data: stop_order };
+        engine.process("test_client".to_string(), message).await;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let message = MessageFromApi::PriceUpdated {
+            market: "SOL_USDC".to_string(),
+        };
+        engine.process("".to_string(), message).await;
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.trigger_orders.len(), 0);
+        assert_eq!(orderbook.bids.len(), 0, "Stop order should fill the bid");
+        assert_eq!(orderbook.asks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_trigger_order() {
+        let mut engine = Engine::new();
+
+        let take_profit = CreateOrderPayload {
+            trigger_price: Some(dec!(1000)),
+            ..order(
+                "1",
+                OrderSide::Sell,
+                OrderType::TakeProfit,
+                dec!(0),
+                dec!(1),
+            )
+        };
+
+        let message = MessageFromApi::CreateOrder { data: take_profit };
+        engine.process("test_client".to_string(), message).await;
+
+        let order_id = {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.asks.len(), 0, "Trigger orders stay off the book");
+            orderbook.trigger_orders[0].id.clone()
+        };
+
+        let cancel_order = CancelOrderPayload {
+            order_id,
+            user_id: "1".to_string(),
+            market: "SOL_USDC".to_string(),
+        };
+
+        let message = MessageFromApi::CancelOrder { data: cancel_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.trigger_orders.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_take_triggered_orders() {
+        let mut orderbook = Orderbook::new(MarketSpec::new("SOL", "USDC"));
+
+        let trigger_order =
+            |id: &str, side: OrderSide, order_type: OrderType, trigger: Decimal| Order {
+                id: id.to_string(),
+                user_id: "1".to_string(),
+                price: dec!(0),
+                quantity: dec!(1),
+                side,
+                is_margin: false,
+                leverage: None,
+                timestamp: 0,
+                order_type,
+                trigger_price: Some(trigger),
+                iceberg: None,
+                reserved_margin: dec!(0),
+            };
+
+        orderbook.trigger_orders = vec![
+            trigger_order(
+                "stop_sell",
+                OrderSide::Sell,
+                OrderType::StopMarket,
+                dec!(90),
+            ),
+            trigger_order("stop_buy", OrderSide::Buy, OrderType::StopLimit, dec!(110)),
+            trigger_order("tp_sell", OrderSide::Sell, OrderType::TakeProfit, dec!(110)),
+            trigger_order("tp_buy", OrderSide::Buy, OrderType::TakeProfit, dec!(90)),
+        ];
+
+        assert!(orderbook.take_triggered_orders(dec!(100)).is_empty());
+
+        let triggered = orderbook.take_triggered_orders(dec!(85));
+        let ids: Vec<&str> = triggered.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, vec!["stop_sell", "tp_buy"]);
+
+        let triggered = orderbook.take_triggered_orders(dec!(115));
+        let ids: Vec<&str> = triggered.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, vec!["stop_buy", "tp_sell"]);
+        assert!(orderbook.trigger_orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_trigger_order_without_funds_is_reported() {
+        let mut engine = Engine::new();
+        let placed = engine
+            .create_order(&CreateOrderPayload {
+                trigger_price: Some(dec!(25)),
+                ..order(
+                    "1",
+                    OrderSide::Buy,
+                    OrderType::StopLimit,
+                    dec!(20),
+                    dec!(1000),
+                )
+            })
+            .await
+            .unwrap();
+
+        let orderbook = engine.orderbooks.lock().await["SOL_USDC"].clone();
+        orderbook.lock().await.mark_price = Some(dec!(25));
+        engine.on_price_update("SOL_USDC").await;
+        assert!(orderbook.lock().await.trigger_orders.is_empty());
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        let rejected = &user.rejected_triggers[0];
+        assert_eq!(rejected.order_id, placed.order_id);
+        assert_eq!(rejected.trigger_price, Some(dec!(25)));
+        assert!(
+            rejected.reason.contains("Insufficient"),
+            "{}",
+            rejected.reason
+        );
+    }
+
+    #[tokio::test]
+    async fn test_price_time_priority() {
+        let mut orderbook = Orderbook::new(MarketSpec::new("SOL", "USDC"));
+
+        let resting_order = |id: &str, side: OrderSide, price: Decimal| Order {
+            id: id.to_string(),
+            user_id: "1".to_string(),
+            price,
+            quantity: dec!(1),
+            side,
+            is_margin: false,
+            leverage: None,
+            timestamp: 0,
+            order_type: OrderType::Spot,
+            trigger_price: None,
+            iceberg: None,
+            reserved_margin: dec!(0),
+        };
+
+        orderbook.insert_order(resting_order("bid_19", OrderSide::Buy, dec!(19)));
+        orderbook.insert_order(resting_order("bid_20_a", OrderSide::Buy, dec!(20)));
+        orderbook.insert_order(resting_order("bid_20_b", OrderSide::Buy, dec!(20)));
+        orderbook.insert_order(resting_order("ask_22", OrderSide::Sell, dec!(22)));
+        orderbook.insert_order(resting_order("ask_21", OrderSide::Sell, dec!(21)));
+
+        let bid_ids: Vec<&str> = orderbook.bids.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(bid_ids, vec!["bid_20_a", "bid_20_b", "bid_19"]);
+        assert_eq!(orderbook.asks.best().unwrap().id, "ask_21");
+        assert_eq!(orderbook.bids.best_price(), Some(dec!(20)));
+
+        let removed = orderbook.remove_order("bid_20_a").unwrap();
+        assert_eq!(removed.price, dec!(20));
+        assert_eq!(orderbook.bids.best().unwrap().id, "bid_20_b");
+        assert_eq!(orderbook.bids.len(), 2);
+        assert!(orderbook.remove_order("bid_20_a").is_none());
+
+        orderbook.remove_order("bid_20_b").unwrap();
+        assert_eq!(orderbook.bids.best_price(), Some(dec!(19)));
+
+        let depth = orderbook.get_depth();
+        assert_eq!(depth.orders.len(), 3);
+        assert_eq!(depth.orders[&dec!(19)].quantity, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn test_partial_fill_keeps_queue_position() {
+        let mut engine = Engine::new();
+
+        for quantity in [dec!(2), dec!(1)] {
+            let sell_order = order("1", OrderSide::Sell, OrderType::Spot, dec!(20), quantity);
+
+            let message = MessageFromApi::CreateOrder { data: sell_order };
+            engine.process("test_client".to_string(), message).await;
+        }
+
+        let first_id = {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            orderbook.asks.best().unwrap().id.clone()
+        };
+
+        let buy_order = order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(1));
+
+        let message = MessageFromApi::CreateOrder { data: buy_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.asks.len(), 2);
+        assert_eq!(orderbook.asks.best().unwrap().id, first_id);
+        assert_eq!(orderbook.asks.best().unwrap().quantity, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn test_amend_order_quantity_down_keeps_priority() {
+        let mut engine = Engine::new();
+
+        for user_id in ["1", "2"] {
+            let buy_order = order(user_id, OrderSide::Buy, OrderType::Spot, dec!(20), dec!(2));
+
+            let message = MessageFromApi::CreateOrder { data: buy_order };
+            engine.process("test_client".to_string(), message).await;
+        }
+
+        let order_id = {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            orderbook.bids.best().unwrap().id.clone()
+        };
+
+        let amend = AmendOrderPayload {
+            order_id: order_id.clone(),
+            user_id: "1".to_string(),
+            market: "SOL_USDC".to_string(),
+            price: None,
+            quantity: Some(dec!(1)),
+        };
+
+        let message = MessageFromApi::AmendOrder { data: amend };
+        engine.process("test_client".to_string(), message).await;
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.bids.best().unwrap().id, order_id);
+            assert_eq!(orderbook.bids.best().unwrap().quantity, dec!(1));
+        }
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.locked_balance, dec!(20));
+    }
+
+    #[tokio::test]
+    async fn test_amend_order_price_moves_and_relocks() {
+        let mut engine = Engine::new();
+
+        let sell_order = order("1", OrderSide::Sell, OrderType::Spot, dec!(30), dec!(1));
+
+        let message = MessageFromApi::CreateOrder { data: sell_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let buy_order = order("2", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(2));
+
+        let message = MessageFromApi::CreateOrder { data: buy_order };
+        engine.process("test_client".to_string(), message).await;
+
+        let order_id = {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            orderbook.bids.best().unwrap().id.clone()
+        };
+
+        for price in [dec!(25), dec!(30)] {
+            let amend = AmendOrderPayload {
+                order_id: order_id.clone(),
+                user_id: "2".to_string(),
+                market: "SOL_USDC".to_string(),
+                price: Some(price),
+                quantity: None,
+            };
+
+            let message = MessageFromApi::AmendOrder { data: amend };
+            engine.process("test_client".to_string(), message).await;
+        }
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            let bid = orderbook.bids.best().unwrap();
+            assert_eq!(bid.id, order_id);
+            assert_eq!(bid.price, dec!(25), "Crossing amend should be rejected");
+            assert_eq!(orderbook.asks.len(), 1);
+        }
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "2").unwrap();
+        let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.locked_balance, dec!(50));
+    }
+
+    #[tokio::test]
+    async fn test_amend_order_checks_market_rules() {
+        let mut engine = Engine::new();
+        // A trade at 20 centres the price band
+        for (user_id, side) in [("1", OrderSide::Sell), ("2", OrderSide::Buy)] {
+            engine
+                .create_order(&order(user_id, side, OrderType::Spot, dec!(20), dec!(1)))
+                .await
+                .unwrap();
+        }
+        let placed = engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(20),
+                dec!(2),
+            ))
+            .await
+            .unwrap();
+
+        let amend = |price: Decimal, quantity: Decimal| AmendOrderPayload {
+            order_id: placed.order_id.clone(),
+            user_id: "2".to_string(),
+            market: "SOL_USDC".to_string(),
+            price: Some(price),
+            quantity: Some(quantity),
+        };
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let mut orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            orderbook.spec.price_band.band_pct = dec!(0.2);
+        }
+        // 0.04 at 20 is a notional of 0.8, below the minimum of 1
+        for (price, quantity, reason) in [
+            (dec!(20), dec!(0.04), "notional"),
+            (dec!(0.5), dec!(1), "notional"),
+            (dec!(20.001), dec!(1), "tick size"),
+            (dec!(35), dec!(1), "price band"),
+        ] {
+            let err = engine
+                .amend_order(&amend(price, quantity))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains(reason), "{}", err);
+        }
+
+        let users = engine.users.read().await;
+        let usdc = users[1]
+            .balances
+            .iter()
+            .find(|b| b.ticker == "USDC")
+            .unwrap();
+        assert_eq!(usdc.locked_balance, dec!(40));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_orders() {
+        let mut engine = Engine::new();
+
+        let orders = [
+            ("SOL_USDC", OrderSide::Buy, dec!(20)),
+            ("SOL_USDC", OrderSide::Sell, dec!(30)),
+            ("BTC_USDC", OrderSide::Buy, dec!(10)),
+        ];
+
+        for (market, side, price) in orders {
+            let order = CreateOrderPayload {
+                market: market.to_string(),
+                ..order("1", side, OrderType::Spot, price, dec!(1))
+            };
+
+            let message = MessageFromApi::CreateOrder { data: order };
+            engine.process("test_client".to_string(), message).await;
+        }
+
+        let cancelled = engine
+            .cancel_all_orders(&CancelAllOrdersPayload {
+                user_id: "1".to_string(),
+                market: Some("SOL_USDC".to_string()),
+                side: Some(OrderSide::Buy),
+            })
+            .await
+            .unwrap();
+        assert_eq!(cancelled.len(), 1);
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.bids.len(), 0);
+            assert_eq!(orderbook.asks.len(), 1);
+        }
+
+        let cancelled = engine
+            .cancel_all_orders(&CancelAllOrdersPayload {
+                user_id: "1".to_string(),
+                market: None,
+                side: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(cancelled.len(), 2);
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        for balance in &user.balances {
+            assert_eq!(
+                balance.locked_balance,
+                dec!(0),
+                "{} still locked",
+                balance.ticker
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_all_orders_releases_margin() {
+        let mut engine = Engine::new();
+        for (side, order_type, price) in [
+            (OrderSide::Buy, OrderType::MarginLong, dec!(20)),
+            (OrderSide::Sell, OrderType::MarginShort, dec!(30)),
+        ] {
+            engine
+                .create_order(&CreateOrderPayload {
+                    is_margin: true,
+                    leverage: Some(dec!(5)),
+                    ..order("2", side, order_type, price, dec!(2))
+                })
+                .await
+                .unwrap();
+        }
+        // 8 for the long and 13.2 for the short, with its 1.1 safety margin
+        assert_eq!(locked_balances(&engine, "2").await, (dec!(0), dec!(21.2)));
+
+        // Half the long fills and its 4 stays locked as collateral
+        engine
+            .create_order(&order(
+                "1",
+                OrderSide::Sell,
+                OrderType::Spot,
+                dec!(20),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+
+        let cancelled = engine
+            .cancel_all_orders(&CancelAllOrdersPayload {
+                user_id: "2".to_string(),
+                market: None,
+                side: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert_eq!(locked_balances(&engine, "2").await, (dec!(0), dec!(4)));
+    }
+
+    fn self_trade_order(
+        side: OrderSide,
+        quantity: Decimal,
+        self_trade_prevention: Option<SelfTradePrevention>,
+    ) -> CreateOrderPayload {
+        CreateOrderPayload {
+            self_trade_prevention,
+            ..order("1", side, OrderType::Spot, dec!(30), quantity)
+        }
+    }
+
+    async fn locked_balances(engine: &Engine, user_id: &str) -> (Decimal, Decimal) {
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == user_id).unwrap();
+        let locked = |ticker: &str| {
+            user.balances
+                .iter()
+                .find(|b| b.ticker == ticker)
+                .unwrap()
+                .locked_balance
+        };
+        (locked("SOL"), locked("USDC"))
+    }
+
+    #[tokio::test]
+    async fn test_self_trade_cancel_oldest() {
+        let mut engine = Engine::new();
+
+        engine
+            .create_order(&self_trade_order(OrderSide::Sell, dec!(1), None))
+            .await
+            .unwrap();
+        let placed = engine
+            .create_order(&self_trade_order(OrderSide::Buy, dec!(1), None))
+            .await
+            .unwrap();
+
+        assert_eq!(placed.filled_qty, dec!(0));
+        assert_eq!(placed.prevented_matches.len(), 1);
+        assert_eq!(
+            placed.prevented_matches[0].mode,
+            SelfTradePrevention::CancelOldest
+        );
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert!(orderbook.asks.is_empty());
+            assert_eq!(orderbook.bids.len(), 1);
+        }
+
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(0), dec!(30)));
+    }
+
+    #[tokio::test]
+    async fn test_self_trade_cancel_newest() {
+        let mut engine = Engine::new();
+
+        engine
+            .create_order(&self_trade_order(OrderSide::Sell, dec!(1), None))
+            .await
+            .unwrap();
+        let placed = engine
+            .create_order(&self_trade_order(
+                OrderSide::Buy,
+                dec!(1),
+                Some(SelfTradePrevention::CancelNewest),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(placed.filled_qty, dec!(0));
+        assert_eq!(placed.prevented_matches.len(), 1);
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.asks.len(), 1);
+            assert!(orderbook.bids.is_empty());
+        }
+
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(1), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_self_trade_decrement_and_cancel() {
+        let mut engine = Engine::new();
+
+        engine
+            .create_order(&self_trade_order(OrderSide::Sell, dec!(3), None))
+            .await
+            .unwrap();
+        let placed = engine
+            .create_order(&self_trade_order(
+                OrderSide::Buy,
+                dec!(1),
+                Some(SelfTradePrevention::DecrementAndCancel),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(placed.prevented_matches[0].quantity, dec!(1));
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.asks.best().unwrap().quantity, dec!(2));
+            assert!(orderbook.bids.is_empty());
+        }
+
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(2), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_self_trade_against_margin_order_releases_its_margin() {
+        let mut engine = Engine::new();
+        engine
+            .create_order(&CreateOrderPayload {
+                is_margin: true,
+                leverage: Some(dec!(5)),
+                ..order(
+                    "1",
+                    OrderSide::Sell,
+                    OrderType::MarginShort,
+                    dec!(30),
+                    dec!(2),
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(0), dec!(13.2)));
+
+        engine
+            .create_order(&self_trade_order(
+                OrderSide::Buy,
+                dec!(1),
+                Some(SelfTradePrevention::DecrementAndCancel),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(0), dec!(6.6)));
+    }
+
+    #[tokio::test]
+    async fn test_market_order_reserves_past_own_orders() {
+        let mut engine = Engine::new();
+        engine
+            .create_order(&order(
+                "1",
+                OrderSide::Sell,
+                OrderType::Spot,
+                dec!(20),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+        engine
+            .create_order(&order(
+                "2",
+                OrderSide::Sell,
+                OrderType::Spot,
+                dec!(25),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+
+        // The own ask at 20 is cancelled, so the buy fills at 25
+        let placed = engine
+            .create_order(&order(
+                "1",
+                OrderSide::Buy,
+                OrderType::Market,
+                dec!(0),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(placed.filled_qty, dec!(1));
+        assert_eq!(placed.fills[0].price, dec!(25));
+
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(0), dec!(0)));
+        assert_eq!(balance(&engine, "1", "USDC").await, dec!(9975));
+    }
+
+    #[tokio::test]
+    async fn test_iceberg_order_replenishes_and_loses_priority() {
+        let mut engine = Engine::new();
+
+        let sell_order =
+            |quantity: Decimal, display_quantity: Option<Decimal>| CreateOrderPayload {
+                display_quantity,
+                ..order("1", OrderSide::Sell, OrderType::Spot, dec!(30), quantity)
+            };
+
+        let iceberg_id = engine
+            .create_order(&sell_order(dec!(10), Some(dec!(2))))
+            .await
+            .unwrap()
+            .order_id;
+        let plain_id = engine
+            .create_order(&sell_order(dec!(1), None))
+            .await
+            .unwrap()
+            .order_id;
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            let depth = orderbook.get_depth();
+            assert_eq!(depth.orders.get(&dec!(30)).unwrap().quantity, dec!(3));
+            assert_eq!(orderbook.asks.best().unwrap().id, iceberg_id);
+
+            let quote = orderbook.get_quote_detail(dec!(5), OrderSide::Buy);
+            assert_eq!(
+                quote.total_cost,
+                dec!(90),
+                "Hidden reserve should not be quoted"
+            );
+        }
+
+        let buy_order = order("2", OrderSide::Buy, OrderType::Spot, dec!(30), dec!(2));
+        let placed = engine.create_order(&buy_order).await.unwrap();
+        assert_eq!(placed.filled_qty, dec!(2));
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        let depth = orderbook.get_depth();
+        assert_eq!(depth.orders.get(&dec!(30)).unwrap().quantity, dec!(3));
+        assert_eq!(
+            orderbook.asks.best().unwrap().id,
+            plain_id,
+            "Replenished slice should queue behind the plain order"
+        );
+
+        let iceberg = orderbook.get_order(&iceberg_id).unwrap();
+        assert_eq!(iceberg.quantity, dec!(8));
+        assert_eq!(iceberg.visible_quantity(), dec!(2));
+    }
+
+    #[tokio::test]
+    async fn test_market_spec_rejects_invalid_orders() {
+        let mut engine = Engine::new();
+
+        let cases = [
+            (dec!(20.005), dec!(1), "tick size"),
+            (dec!(20), dec!(0.005), "below the minimum"),
+            (dec!(20), dec!(1.005), "lot size"),
+            (dec!(0.5), dec!(1), "notional"),
+            (dec!(20), dec!(2_000_000), "maximum order size"),
+        ];
+
+        for (price, quantity, reason) in cases {
+            let err = engine
+                .create_order(&order(
+                    "1",
+                    OrderSide::Buy,
+                    OrderType::Spot,
+                    price,
+                    quantity,
+                ))
+                .await
+                .unwrap_err();
+            assert!(
+                err.to_string().contains(reason),
+                "expected '{}' rejection, got '{}'",
+                reason,
+                err
+            );
+        }
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert!(orderbook.bids.is_empty());
+    }
+
+    fn with_market_registry(engine: &mut Engine) {
+        let path = std::env::temp_dir().join(format!("markets-{}.json", Uuid::new_v4()));
+        engine.market_registry = Arc::new(MarketRegistry::new(Some(path)));
+    }
+
+    #[tokio::test]
+    async fn test_create_market_requires_market_registry() {
+        let mut engine = Engine::new();
+        let payload = CreateMarketPayload {
+            base_asset: "doge".to_string(),
+            quote_asset: "usdc".to_string(),
+            tick_size: None,
+            lot_size: None,
+            min_quantity: None,
+            min_notional: None,
+            max_order_size: None,
+            product: None,
+            self_trade_prevention: None,
+        };
+
+        let err = engine.create_market(&payload).await.unwrap_err();
+        assert!(err.to_string().contains("MARKETS_CONFIG"));
+        assert!(!engine.orderbooks.lock().await.contains_key("DOGE_USDC"));
+    }
+
+    #[tokio::test]
+    async fn test_create_market() {
+        let mut engine = Engine::new();
+        with_market_registry(&mut engine);
+
+        let create_market = |base_asset: &str| CreateMarketPayload {
+            base_asset: base_asset.to_string(),
+            quote_asset: "usdc".to_string(),
+            tick_size: Some(dec!(0.0001)),
+            lot_size: None,
+            min_quantity: None,
+            min_notional: None,
+            max_order_size: None,
+            product: None,
+            self_trade_prevention: Some(SelfTradePrevention::CancelNewest),
+        };
+
+        let spec = engine.create_market(&create_market("doge")).await.unwrap();
+        assert_eq!(spec.market, "DOGE_USDC");
+        assert_eq!(spec.tick_size, dec!(0.0001));
+        assert_eq!(
+            spec.self_trade_prevention,
+            SelfTradePrevention::CancelNewest
+        );
+
+        assert!(engine.create_market(&create_market("DOGE")).await.is_err());
+        assert!(engine.create_market(&create_market("DO_GE")).await.is_err());
+
+        let buy_order = CreateOrderPayload {
+            market: "DOGE_USDC".to_string(),
+            ..order(
+                "1",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(0.1234),
+                dec!(100),
+            )
+        };
+        engine.create_order(&buy_order).await.unwrap();
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("DOGE_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.bids.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_market_locks_its_own_quote_asset() {
+        let mut engine = Engine::new();
+        with_market_registry(&mut engine);
+        engine
+            .create_market(&CreateMarketPayload {
+                base_asset: "sol".to_string(),
+                quote_asset: "eur".to_string(),
+                tick_size: None,
+                lot_size: None,
+                min_quantity: None,
+                min_notional: None,
+                max_order_size: None,
+                product: None,
+                self_trade_prevention: None,
+            })
+            .await
+            .unwrap();
+
+        let buy_order = CreateOrderPayload {
+            market: "SOL_EUR".to_string(),
+            ..order("1", OrderSide::Buy, OrderType::Spot, dec!(20), dec!(1))
+        };
+        assert!(
+            engine.create_order(&buy_order).await.is_err(),
+            "A USDC balance should not fund a EUR market"
+        );
+
+        {
+            let mut users = engine.users.write().await;
+            let user = users.iter_mut().find(|u| u.id == "1").unwrap();
+            user.credit("EUR", dec!(100));
+        }
+        engine.create_order(&buy_order).await.unwrap();
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        let locked = |ticker: &str| {
+            user.balances
+                .iter()
+                .find(|b| b.ticker == ticker)
+                .unwrap()
+                .locked_balance
+        };
+        assert_eq!(locked("EUR"), dec!(20));
+        assert_eq!(locked("USDC"), dec!(0));
+    }
+
+    #[test]
+    fn test_market_registry_config_matches_defaults() {
+        let registry = MarketRegistry::new(Some("markets.json".into()));
+        let loaded = registry.load();
+        let defaults = MarketRegistry::default_markets();
+
+        assert_eq!(loaded.len(), defaults.len());
+        for (loaded, default) in loaded.iter().zip(defaults.iter()) {
+            assert_eq!(loaded.market, default.market);
+            assert_eq!(loaded.tick_size, default.tick_size);
+            assert_eq!(loaded.lot_size, default.lot_size);
+            assert_eq!(loaded.min_quantity, default.min_quantity);
+            assert_eq!(loaded.min_notional, default.min_notional);
+            assert_eq!(loaded.max_order_size, default.max_order_size);
+            assert_eq!(loaded.self_trade_prevention, default.self_trade_prevention);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_market_status_lifecycle() {
+        let mut engine = Engine::new();
+
+        let spot_order = |user_id: &str, side: OrderSide, price: Decimal| {
+            order(user_id, side, OrderType::Spot, price, dec!(1))
+        };
+        let set_status = |status: MarketStatus| SetMarketStatusPayload {
+            market: "SOL_USDC".to_string(),
+            status,
+        };
+
+        let ask_id = engine
+            .create_order(&spot_order("1", OrderSide::Sell, dec!(30)))
+            .await
+            .unwrap()
+            .order_id;
+
+        engine
+            .set_market_status(&set_status(MarketStatus::Halted))
+            .await
+            .unwrap();
+        assert!(engine
+            .create_order(&spot_order("2", OrderSide::Buy, dec!(20)))
+            .await
+            .is_err());
+        let cancel = CancelOrderPayload {
+            order_id: ask_id.clone(),
+            user_id: "1".to_string(),
+            market: "SOL_USDC".to_string(),
+        };
+        assert!(engine.cancel_order(&cancel).await.is_err());
+
+        engine
+            .set_market_status(&set_status(MarketStatus::PostOnly))
+            .await
+            .unwrap();
+        assert!(engine
+            .create_order(&spot_order("2", OrderSide::Buy, dec!(30)))
+            .await
+            .is_err());
+        engine
+            .create_order(&spot_order("2", OrderSide::Buy, dec!(20)))
+            .await
+            .unwrap();
+        // Delisting must release this margin order's 8.8 USDC, not 1 SOL
+        engine
+            .create_order(&CreateOrderPayload {
+                is_margin: true,
+                leverage: Some(dec!(5)),
+                ..order(
+                    "1",
+                    OrderSide::Sell,
+                    OrderType::MarginShort,
+                    dec!(40),
+                    dec!(1),
+                )
+            })
+            .await
+            .unwrap();
+
+        engine
+            .set_market_status(&set_status(MarketStatus::CancelOnly))
+            .await
+            .unwrap();
+        assert!(engine
+            .create_order(&spot_order("2", OrderSide::Buy, dec!(20)))
+            .await
+            .is_err());
+        engine.cancel_order(&cancel).await.unwrap();
+
+        engine
+            .set_market_status(&set_status(MarketStatus::Delisted))
+            .await
+            .unwrap();
+        assert!(engine
+            .set_market_status(&set_status(MarketStatus::Trading))
+            .await
+            .is_err());
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert!(orderbook.bids.is_empty());
+            assert!(orderbook.asks.is_empty());
+        }
+
+        assert_eq!(locked_balances(&engine, "1").await, (dec!(0), dec!(0)));
+        assert_eq!(locked_balances(&engine, "2").await, (dec!(0), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_price_band_rejects_and_stops_sweeps() {
+        let mut engine = Engine::new();
+
+        for price in [dec!(30), dec!(44)] {
+            engine
+                .create_order(&order(
+                    "1",
+                    OrderSide::Sell,
+                    OrderType::Spot,
+                    price,
+                    dec!(1),
+                ))
+                .await
+                .unwrap();
+        }
+        engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(30),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let mut orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            orderbook.spec.price_band.band_pct = dec!(0.2);
+            assert_eq!(orderbook.price_band_limits(), Some((dec!(24), dec!(36))));
+        }
+
+        let err = engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(44),
+                dec!(1),
+            ))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("price band"));
+
+        let placed = engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Market,
+                dec!(0),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(placed.filled_qty, dec!(0));
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        assert_eq!(orderbook.asks.best().unwrap().price, dec!(44));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_halts_and_resumes() {
+        let mut engine = Engine::new();
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let mut orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            orderbook.spec.price_band.halt_move_pct = dec!(0.1);
+        }
+
+        for price in [dec!(30), dec!(34)] {
+            engine
+                .create_order(&order(
+                    "1",
+                    OrderSide::Sell,
+                    OrderType::Spot,
+                    price,
+                    dec!(1),
+                ))
+                .await
+                .unwrap();
+            engine
+                .create_order(&order("2", OrderSide::Buy, OrderType::Spot, price, dec!(1)))
+                .await
+                .unwrap();
+        }
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert_eq!(orderbook.status, MarketStatus::Halted);
+            assert!(orderbook.circuit_breaker.halted_until.is_some());
+        }
+
+        assert!(engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(34),
+                dec!(1)
+            ))
+            .await
+            .is_err());
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let mut orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        let halted_until = orderbook.circuit_breaker.halted_until.unwrap();
+        assert!(!orderbook.resume_after_cooldown(halted_until - 1));
+        assert!(orderbook.resume_after_cooldown(halted_until));
+        assert_eq!(orderbook.status, MarketStatus::Trading);
+    }
+
+    async fn balance(engine: &Engine, user_id: &str, ticker: &str) -> Decimal {
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == user_id).unwrap();
+        user.balances
+            .iter()
+            .find(|b| b.ticker == ticker)
+            .map_or(Decimal::ZERO, |b| b.balance)
+    }
+
+    #[tokio::test]
+    async fn test_fees_charged_and_credited() {
+        let mut engine = Engine::new();
+
+        engine
+            .create_order(&order(
+                "1",
+                OrderSide::Sell,
+                OrderType::Spot,
+                dec!(30),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+        let placed = engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(30),
+                dec!(1),
+            ))
+            .await
+            .unwrap();
+
+        // The taker buys SOL and pays 0.2% of it, the maker pays 0.1% of the USDC
+        assert_eq!(placed.fee, dec!(0.002));
+        assert_eq!(placed.fee_asset, "SOL");
+        assert_eq!(balance(&engine, "2", "SOL").await, dec!(100.998));
+        assert_eq!(balance(&engine, "1", "USDC").await, dec!(10029.97));
+        assert_eq!(
+            balance(&engine, EXCHANGE_FEE_ACCOUNT, "SOL").await,
+            dec!(0.002)
+        );
+        assert_eq!(
+            balance(&engine, EXCHANGE_FEE_ACCOUNT, "USDC").await,
+            dec!(0.03)
+        );
+
+        let users = engine.users.read().await;
+        let now = Utc::now().timestamp();
+        for user_id in ["1", "2"] {
+            let user = users.iter().find(|u| u.id == user_id).unwrap();
+            assert_eq!(user.rolling_volume(now), dec!(30));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fee_tiers_and_maker_rebate() {
+        let schedule = MarketSpec::new("SOL", "USDC").fees;
+        assert_eq!(schedule.rates(dec!(0)), (dec!(0.001), dec!(0.002)));
+        assert_eq!(schedule.rates(dec!(250_000)), (dec!(0.0005), dec!(0.0015)));
+        assert_eq!(
+            schedule.rates(dec!(20_000_000)),
+            (dec!(-0.0001), dec!(0.0008))
+        );
+
+        let mut engine = Engine::new();
+        let now = Utc::now().timestamp();
+        {
+            let mut users = engine.users.write().await;
+            let maker = users.iter_mut().find(|u| u.id == "1").unwrap();
+            maker.record_volume(now - 40 * 86_400, dec!(50_000_000));
+            maker.record_volume(now, dec!(10_000_000));
+            assert_eq!(maker.rolling_volume(now), dec!(10_000_000));
+        }
+
+        let trade = |engine: &mut Engine| {
+            let mut engine = engine.clone();
+            async move {
+                for (user_id, side) in [("1", OrderSide::Sell), ("2", OrderSide::Buy)] {
+                    engine
+                        .create_order(&order(user_id, side, OrderType::Spot, dec!(30), dec!(1)))
+                        .await
+                        .unwrap();
+                }
+            }
+        };
+
+        // The taker's fee is paid in SOL, so there is no USDC to pay a rebate from
+        trade(&mut engine).await;
+        assert_eq!(balance(&engine, "1", "USDC").await, dec!(10030));
+        assert_eq!(
+            balance(&engine, EXCHANGE_FEE_ACCOUNT, "USDC").await,
+            dec!(0)
+        );
+
+        {
+            let mut users = engine.users.write().await;
+            let fee_account = users
+                .iter_mut()
+                .find(|u| u.id == EXCHANGE_FEE_ACCOUNT)
+                .unwrap();
+            fee_account.credit("USDC", dec!(1));
+        }
+        trade(&mut engine).await;
+        assert_eq!(balance(&engine, "1", "USDC").await, dec!(10060.003));
+        assert_eq!(
+            balance(&engine, EXCHANGE_FEE_ACCOUNT, "USDC").await,
+            dec!(0.997)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_order_response_lists_fills() {
+        let mut engine = Engine::new();
+
+        let mut maker_ids = Vec::new();
+        for price in [dec!(30), dec!(31)] {
+            let placed = engine
+                .create_order(&order(
+                    "1",
+                    OrderSide::Sell,
+                    OrderType::Spot,
+                    price,
+                    dec!(1),
+                ))
+                .await
+                .unwrap();
+            maker_ids.push(placed.order_id);
+        }
+
+        let taker = order("2", OrderSide::Buy, OrderType::Spot, dec!(31), dec!(2));
+        let placed = engine.create_order(&taker).await.unwrap();
+
+        assert_eq!(placed.fills.len(), 2);
+        for (fill, (maker_id, price)) in placed
+            .fills
+            .iter()
+            .zip(maker_ids.iter().zip([dec!(30), dec!(31)]))
+        {
+            assert_eq!(&fill.maker_order_id, maker_id);
+            assert_eq!(fill.taker_order_id, placed.order_id);
+            assert_eq!(fill.market, "SOL_USDC");
+            assert_eq!(fill.price, price);
+            assert_eq!(fill.quantity, dec!(1));
+            assert_eq!(fill.taker_side, OrderSide::Buy);
+            assert_eq!(fill.taker_fee, dec!(0.002));
+            assert_eq!(fill.taker_fee_asset, "SOL");
+            assert_eq!(fill.maker_fee, price * dec!(0.001));
+            assert_eq!(fill.maker_fee_asset, "USDC");
+        }
+        assert_ne!(placed.fills[0].trade_id, placed.fills[1].trade_id);
+        assert_eq!(placed.fee, dec!(0.004));
+    }
+
+    #[tokio::test]
+    async fn test_ticker_tracks_last_trade_and_24h_stats() {
+        let mut engine = Engine::new();
+
+        {
+            let orderbooks = engine.orderbooks.lock().await;
+            let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+            assert!(orderbook.get_price_info().await.is_none());
+        }
+
+        for price in [dec!(30), dec!(33), dec!(31)] {
+            engine
+                .create_order(&order(
+                    "1",
+                    OrderSide::Sell,
+                    OrderType::Spot,
+                    price,
+                    dec!(1),
+                ))
+                .await
+                .unwrap();
+            engine
+                .create_order(&order("2", OrderSide::Buy, OrderType::Spot, price, dec!(1)))
+                .await
+                .unwrap();
+        }
+
+        let orderbooks = engine.orderbooks.lock().await;
+        let orderbook = orderbooks.get("SOL_USDC").unwrap().lock().await;
+        let ticker = orderbook.ticker().await;
+        let price = ticker.price.unwrap();
+        assert_eq!(price.last_trade_price, Some(dec!(31)));
+        assert_eq!(
+            price.mark_price,
+            dec!(31),
+            "Empty book marks at the last trade"
+        );
+
+        assert_eq!(ticker.stats.open, Some(dec!(30)));
+        assert_eq!(ticker.stats.high, Some(dec!(33)));
+        assert_eq!(ticker.stats.low, Some(dec!(30)));
+        assert_eq!(ticker.stats.close, Some(dec!(31)));
+        assert_eq!(ticker.stats.base_volume, dec!(3));
+        assert_eq!(ticker.stats.quote_volume, dec!(94));
+        assert_eq!(ticker.stats.trade_count, 3);
+    }
+
+    #[test]
+    fn test_trade_history_rolls_off_after_24h() {
+        let mut history = TradeHistory::new();
+        let now = 1_700_000_000;
+
+        history.record(dec!(10), dec!(1), now - 90_000);
+        history.record(dec!(12), dec!(2), now - 3_600);
+
+        let stats = history.stats_24h(now);
+        assert_eq!(stats.open, Some(dec!(12)));
+        assert_eq!(stats.base_volume, dec!(2));
+        assert_eq!(stats.trade_count, 1);
+
+        let stats = history.stats_24h(now + 86_400);
+        assert_eq!(stats.trade_count, 0);
+        assert_eq!(stats.close, None);
+        assert_eq!(history.last_price(), Some(dec!(12)));
+    }
+
+    struct FixedIndexSource(Option<Decimal>);
+
+    impl IndexPriceSource for FixedIndexSource {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn fetch(&self, _market: &str) -> Option<Decimal> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_index_price_is_median_of_sources() {
+        let path = std::env::temp_dir().join(format!("index_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"SOL_USDC": "104"}"#).unwrap();
+
+        let price_service = PriceService::with_index_sources(vec![
+            Box::new(FixedIndexSource(Some(dec!(100)))),
+            Box::new(FixedIndexSource(None)),
+            Box::new(FixedIndexSource(Some(dec!(101)))),
+            Box::new(FileIndexSource::new(path.clone())),
+        ]);
+        assert_eq!(price_service.index_price("SOL_USDC"), Some(dec!(101)));
+        assert_eq!(price_service.index_price("BTC_USDC"), Some(dec!(100.5)));
+
+        std::fs::remove_file(path).unwrap();
+        assert!(PriceService::with_index_sources(Vec::new())
+            .index_price("SOL_USDC")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mark_price_clamps_basis_to_index() {
+        let mut orderbook = Orderbook::new(MarketSpec::new("SOL", "USDC"));
+        orderbook.index_price = Some(dec!(100));
+        assert_eq!(
+            orderbook.get_price_info().await.unwrap().mark_price,
+            dec!(100)
+        );
+
+        for (side, price) in [(OrderSide::Buy, dec!(119)), (OrderSide::Sell, dec!(121))] {
+            orderbook.insert_order(Order {
+                id: format!("{:?}", side),
+                user_id: "1".to_string(),
+                price,
+                quantity: dec!(1),
+                side,
+                is_margin: false,
+                leverage: None,
+                timestamp: 0,
+                order_type: OrderType::Spot,
+                trigger_price: None,
+                iceberg: None,
+                reserved_margin: dec!(0),
+            });
+        }
+
+        let price_info = orderbook.get_price_info().await.unwrap();
+        assert_eq!(price_info.index_price, Some(dec!(100)));
+        assert_eq!(
+            price_info.mark_price,
+            dec!(101),
+            "A 20% premium is capped at 1%"
+        );
+
+        orderbook.index_price = Some(dec!(120.5));
+        assert_eq!(
+            orderbook.get_price_info().await.unwrap().mark_price,
+            dec!(120)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_perpetual_funding_settles_between_longs_and_shorts() {
+        let mut engine = Engine::new();
+        with_market_registry(&mut engine);
+        let spec = engine
+            .create_market(&CreateMarketPayload {
+                base_asset: "avax".to_string(),
+                quote_asset: "usdc".to_string(),
+                tick_size: None,
+                lot_size: None,
+                min_quantity: None,
+                min_notional: None,
+                max_order_size: None,
+                product: Some(ProductType::Perpetual),
+                self_trade_prevention: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(spec.product, ProductType::Perpetual);
+
+        {
+            let mut users = engine.users.write().await;
+            for (user_id, position_type) in [("1", PositionType::Long), ("2", PositionType::Short)]
+            {
+                let user = users.iter_mut().find(|u| u.id == user_id).unwrap();
+                user.margin_positions.push(MarginPosition {
+                    asset: "AVAX_USDC".to_string(),
+                    user_id: user_id.to_string(),
+                    position_type,
+                    entry_price: dec!(20),
+                    size: dec!(10),
+                    leverage: dec!(2),
+                    collateral: dec!(100),
+                    unrealized_pnl: dec!(0),
+                    adl_rank: None,
+                    liquidation_price: None,
+                    margin_ratio: None,
+                });
+                let usdc = user
+                    .balances
+                    .iter_mut()
+                    .find(|b| b.ticker == "USDC")
+                    .unwrap();
+                usdc.locked_balance = dec!(100);
+            }
+        }
+
+        let interval = spec.funding.interval_secs;
+        let start = 1_700_000_000 / interval * interval + 1;
+        let funding_rate = {
+            let orderbooks = engine.orderbooks.lock().await;
+            let mut orderbook = orderbooks.get("AVAX_USDC").unwrap().lock().await;
+            orderbook.index_price = Some(dec!(20));
+
+            assert!(orderbook.accrue_funding(dec!(20.02), start).is_none());
+            assert!(orderbook.accrue_funding(dec!(20.06), start + 1).is_none());
+            let funding_rate = orderbook
+                .accrue_funding(dec!(20.04), start + interval)
+                .unwrap();
+            assert_eq!(orderbook.funding.history.len(), 1);
+            funding_rate
+        };
+
+        // Mark averaged 0.2% over the index
+        assert_eq!(funding_rate.premium_index, dec!(0.002));
+        assert_eq!(funding_rate.rate, dec!(0.002));
+        assert_eq!(funding_rate.timestamp, start - 1 + interval);
+
+        engine.settle_funding(&funding_rate).await;
+
+        let users = engine.users.read().await;
+        for (user_id, collateral, usdc_balance) in [
+            ("1", dec!(99.5992), dec!(9999.5992)),
+            ("2", dec!(100.4008), dec!(10000.4008)),
+        ] {
+            let user = users.iter().find(|u| u.id == user_id).unwrap();
+            assert_eq!(user.margin_positions[0].collateral, collateral);
+            let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+            assert_eq!(usdc.balance, usdc_balance);
+            assert_eq!(usdc.locked_balance, collateral);
+        }
+    }
+
+    #[test]
+    fn test_funding_rate_is_capped() {
+        let config = FundingConfig {
+            interval_secs: 60,
+            max_rate: dec!(0.001),
+        };
+        let mut funding = Funding::new();
+
+        funding.sample_premium(dec!(90), dec!(100));
+        assert!(funding
+            .settle("SOL_USDC", &config, dec!(90), dec!(100), 30)
+            .is_none());
+
+        let funding_rate = funding
+            .settle("SOL_USDC", &config, dec!(90), dec!(100), 61)
+            .unwrap();
+        assert_eq!(funding_rate.premium_index, dec!(-0.1));
+        assert_eq!(funding_rate.rate, dec!(-0.001));
+        assert_eq!(funding_rate.timestamp, 60);
+
+        assert!(
+            funding
+                .settle("SOL_USDC", &config, dec!(90), dec!(100), 125)
+                .is_none(),
+            "Nothing is settled without premium samples"
+        );
+    }
+
+    /// User 1's long with a margin bid from user 2 at `bid_price`.
+    async fn liquidation_setup(engine: &mut Engine, bid_price: Decimal) {
+        open_long(engine).await;
+
+        engine
+            .create_order(&CreateOrderPayload {
+                is_margin: true,
+                leverage: Some(dec!(5)),
+                ..order(
+                    "2",
+                    OrderSide::Buy,
+                    OrderType::MarginLong,
+                    bid_price,
+                    dec!(5),
+                )
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Gives user 1 a 5 SOL long at 20 with 5x leverage.
+    async fn open_long(engine: &mut Engine) {
+        let mut users = engine.users.write().await;
+        let user = users.iter_mut().find(|u| u.id == "1").unwrap();
+        user.margin_positions.push(MarginPosition {
+            asset: "SOL_USDC".to_string(),
+            user_id: "1".to_string(),
+            position_type: PositionType::Long,
+            entry_price: dec!(20),
+            size: dec!(5),
+            leverage: dec!(5),
+            collateral: dec!(20),
+            unrealized_pnl: dec!(0),
+            adl_rank: None,
+            liquidation_price: None,
+            margin_ratio: None,
+        });
+        user.margin_used = dec!(20);
+        let usdc = user
+            .balances
+            .iter_mut()
+            .find(|b| b.ticker == "USDC")
+            .unwrap();
+        usdc.locked_balance = dec!(20);
+    }
+
+    #[tokio::test]
+    async fn test_margin_order_against_existing_position_locks_no_margin() {
+        let mut engine = Engine::new();
+        open_long(&mut engine).await;
+
+        let short_order = CreateOrderPayload {
+            is_margin: true,
+            leverage: Some(dec!(5)),
+            ..order(
+                "1",
+                OrderSide::Sell,
+                OrderType::MarginShort,
+                dec!(25),
+                dec!(5),
+            )
+        };
+        engine.create_order(&short_order).await.unwrap();
+
+        let (_, usdc_locked) = locked_balances(&engine, "1").await;
+        assert_eq!(usdc_locked, dec!(20));
+    }
+
+    #[tokio::test]
+    async fn test_liquidation_closes_through_book_and_pays_insurance_fund() {
+        let mut engine = Engine::new();
+        liquidation_setup(&mut engine, dec!(16.5)).await;
+
+        // Equity of 20 - 3 * 5 = 5 is still above the 5% maintenance margin at 17
+        assert!(engine
+            .pnl_service
+            .liquidation_candidates("SOL_USDC", dec!(17))
+            .await
+            .is_empty());
+
+        engine.check_liquidations("SOL_USDC", dec!(16.5)).await;
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        assert!(user.margin_positions.is_empty());
+        assert_eq!(user.realized_pnl, dec!(-17.5));
+        assert_eq!(user.margin_used, dec!(0));
+
+        // Loss of 17.5, a 0.165 taker fee and a 0.825 liquidation fee
+        let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.balance, dec!(9981.51));
+        assert_eq!(usdc.locked_balance, dec!(0));
+
+        let counterparty = users.iter().find(|u| u.id == "2").unwrap();
+        assert_eq!(counterparty.margin_positions[0].size, dec!(5));
+        assert_eq!(
+            counterparty.margin_positions[0].position_type,
+            PositionType::Long
+        );
+
+        let fund = users
+            .iter()
+            .find(|u| u.id == INSURANCE_FUND_ACCOUNT)
+            .unwrap();
+        assert_eq!(fund.balances[0].balance, dec!(0.825));
+    }
+
+    #[tokio::test]
+    async fn test_liquidation_against_spot_bid_settles_only_the_bidder() {
+        let mut engine = Engine::new();
+        open_long(&mut engine).await;
+        engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(16.5),
+                dec!(5),
+            ))
+            .await
+            .unwrap();
+
+        engine.check_liquidations("SOL_USDC", dec!(16.5)).await;
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        assert!(user.margin_positions.is_empty());
+
+        // The position settles in USDC alone, spot SOL is left untouched
+        let sol = user.balances.iter().find(|b| b.ticker == "SOL").unwrap();
+        assert_eq!((sol.balance, sol.locked_balance), (dec!(100), dec!(0)));
+        let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(
+            (usdc.balance, usdc.locked_balance),
+            (dec!(9981.51), dec!(0))
+        );
+
+        // The bidder pays 82.5 USDC for 5 SOL less a 0.005 SOL maker fee
+        let bidder = users.iter().find(|u| u.id == "2").unwrap();
+        let sol = bidder.balances.iter().find(|b| b.ticker == "SOL").unwrap();
+        assert_eq!(sol.balance, dec!(104.995));
+        let usdc = bidder.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!((usdc.balance, usdc.locked_balance), (dec!(9917.5), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_bankrupt_liquidation_draws_on_insurance_fund() {
+        let mut engine = Engine::new();
+        {
+            let mut users = engine.users.write().await;
+            let fund = users
+                .iter_mut()
+                .find(|u| u.id == INSURANCE_FUND_ACCOUNT)
+                .unwrap();
+            fund.credit("USDC", dec!(100));
+        }
+        liquidation_setup(&mut engine, dec!(15)).await;
+
+        engine.check_liquidations("SOL_USDC", dec!(15)).await;
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        assert!(user.margin_positions.is_empty());
+
+        // The 25 loss exceeds the 20 of collateral, the fund covers the other 5
+        let usdc = user.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!(usdc.balance, dec!(9979.85));
+
+        let fund = users
+            .iter()
+            .find(|u| u.id == INSURANCE_FUND_ACCOUNT)
+            .unwrap();
+        assert_eq!(fund.balances[0].balance, dec!(95));
+    }
+
+    #[tokio::test]
+    async fn test_reduce_only_order_cannot_grow_position() {
+        let mut engine = Engine::new();
+        liquidation_setup(&mut engine, dec!(16.5)).await;
+
+        let reduce_only = |quantity: Decimal, side: OrderSide| CreateOrderPayload {
+            is_margin: true,
+            leverage: Some(dec!(5)),
+            reduce_only: true,
+            ..order("1", side, OrderType::Market, dec!(0), quantity)
+        };
+
+        for (quantity, side) in [(dec!(6), OrderSide::Sell), (dec!(1), OrderSide::Buy)] {
+            let err = engine
+                .create_order(&reduce_only(quantity, side))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("Reduce-only"));
+        }
+
+        // A reduce-only limit order never rests, so it must say so
+        let err = engine
+            .create_order(&CreateOrderPayload {
+                order_type: OrderType::MarginShort,
+                price: dec!(16.5),
+                ..reduce_only(dec!(2), OrderSide::Sell)
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("IOC or FOK"), "{}", err);
+
+        let placed = engine
+            .create_order(&reduce_only(dec!(2), OrderSide::Sell))
+            .await
+            .unwrap();
+        assert_eq!(placed.filled_qty, dec!(2));
+
+        let users = engine.users.read().await;
+        let position = &users[0].margin_positions[0];
+        assert_eq!(position.size, dec!(3));
+        assert_eq!(position.collateral, dec!(12));
+        assert_eq!(users[0].realized_pnl, dec!(-7));
+    }
+
+    /// Opens a SOL position for `user_id`, creating the user if needed.
+    async fn open_position(
+        engine: &mut Engine,
+        user_id: &str,
+        position_type: PositionType,
+        entry_price: Decimal,
+        size: Decimal,
+        collateral: Decimal,
+    ) {
+        let mut users = engine.users.write().await;
+        if !users.iter().any(|u| u.id == user_id) {
+            let mut user = User::_new(user_id.to_string());
+            user.credit("USDC", dec!(10000));
+            users.push(user);
+        }
+        let user = users.iter_mut().find(|u| u.id == user_id).unwrap();
+        user.margin_positions.push(MarginPosition {
+            asset: "SOL_USDC".to_string(),
+            user_id: user_id.to_string(),
+            position_type,
+            entry_price,
+            size,
+            leverage: entry_price * size / collateral,
+            collateral,
+            unrealized_pnl: dec!(0),
+            adl_rank: None,
+            liquidation_price: None,
+            margin_ratio: None,
+        });
+        user.margin_used += collateral;
+        let usdc = user
+            .balances
+            .iter_mut()
+            .find(|b| b.ticker == "USDC")
+            .unwrap();
+        usdc.locked_balance += collateral;
+    }
+
+    #[tokio::test]
+    async fn test_unabsorbed_liquidation_deleverages_by_adl_rank() {
+        let mut engine = Engine::new();
+        open_position(
+            &mut engine,
+            "1",
+            PositionType::Long,
+            dec!(20),
+            dec!(5),
+            dec!(20),
+        )
+        .await;
+        open_position(
+            &mut engine,
+            "3",
+            PositionType::Short,
+            dec!(25),
+            dec!(4),
+            dec!(10),
+        )
+        .await;
+        open_position(
+            &mut engine,
+            "4",
+            PositionType::Short,
+            dec!(25),
+            dec!(4),
+            dec!(50),
+        )
+        .await;
+
+        {
+            let mut users = engine.users.write().await;
+            rank_adl_queue(&mut users, "SOL_USDC", dec!(16.5));
+            let rank = |user_id: &str| {
+                users
+                    .iter()
+                    .find(|u| u.id == user_id)
+                    .unwrap()
+                    .margin_positions[0]
+                    .adl_rank
+            };
+            // Same PnL, but user 3 holds it on far less collateral
+            assert_eq!(rank("3"), Some(1));
+            assert_eq!(rank("4"), Some(2));
+            assert_eq!(rank("1"), None);
+        }
+
+        // Nothing on the book, so the long closes at its bankruptcy price of 16
+        engine.check_liquidations("SOL_USDC", dec!(16.5)).await;
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        assert!(user.margin_positions.is_empty());
+        assert_eq!(user.realized_pnl, dec!(-20));
+        assert_eq!(user.balances[0].balance, dec!(9980));
+        assert_eq!(user.balances[0].locked_balance, dec!(0));
+
+        let first = users.iter().find(|u| u.id == "3").unwrap();
+        assert!(first.margin_positions.is_empty());
+        assert_eq!(first.realized_pnl, dec!(36));
+        assert_eq!(first.balances[0].balance, dec!(10036));
+        assert_eq!(first.balances[0].locked_balance, dec!(0));
+
+        let second = users.iter().find(|u| u.id == "4").unwrap();
+        assert_eq!(second.margin_positions[0].size, dec!(3));
+        assert_eq!(second.margin_positions[0].collateral, dec!(37.5));
+        assert_eq!(second.realized_pnl, dec!(9));
+        assert_eq!(second.balances[0].locked_balance, dec!(37.5));
+    }
+
+    #[tokio::test]
+    async fn test_liquidation_beyond_insurance_fund_is_deleveraged() {
+        let mut engine = Engine::new();
+        liquidation_setup(&mut engine, dec!(15)).await;
+        open_position(
+            &mut engine,
+            "3",
+            PositionType::Short,
+            dec!(25),
+            dec!(5),
+            dec!(25),
+        )
+        .await;
+
+        // The 5 loss at the mark exceeds the empty insurance fund
+        engine.check_liquidations("SOL_USDC", dec!(15)).await;
+
+        assert_eq!(balance(&engine, "1", "USDC").await, dec!(9980));
+        assert_eq!(balance(&engine, "3", "USDC").await, dec!(10045));
+        assert_eq!(
+            balance(&engine, INSURANCE_FUND_ACCOUNT, "USDC").await,
+            dec!(0)
+        );
+
+        let users = engine.users.read().await;
+        for user_id in ["1", "2", "3"] {
+            let user = users.iter().find(|u| u.id == user_id).unwrap();
+            assert!(
+                user.margin_positions.is_empty(),
+                "The resting bid was not used"
+            );
+        }
+    }
+
+    fn margin_mode(user_id: &str, market: &str, mode: MarginMode) -> SetMarginModePayload {
+        SetMarginModePayload {
+            user_id: user_id.to_string(),
+            market: market.to_string(),
+            mode,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_margin_mode_switch_requires_no_open_position() {
+        let mut engine = Engine::new();
+
+        let mode = engine
+            .set_margin_mode(&margin_mode("1", "SOL_USDC", MarginMode::Cross))
+            .await
+            .unwrap();
+        assert_eq!(mode, MarginMode::Cross);
+        assert!(engine
+            .set_margin_mode(&margin_mode("1", "DOGE_USDC", MarginMode::Cross))
+            .await
+            .is_err());
+
+        open_position(
+            &mut engine,
+            "1",
+            PositionType::Long,
+            dec!(20),
+            dec!(5),
+            dec!(20),
+        )
+        .await;
+        let err = engine
+            .set_margin_mode(&margin_mode("1", "SOL_USDC", MarginMode::Isolated))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("open position"));
+
+        // Other markets can still be switched
+        engine
+            .set_margin_mode(&margin_mode("1", "BTC_USDC", MarginMode::Cross))
+            .await
+            .unwrap();
+        let users = engine.users.read().await;
+        assert_eq!(users[0].margin_mode("SOL_USDC"), MarginMode::Cross);
+        assert_eq!(users[0].margin_mode("BTC_USDC"), MarginMode::Cross);
+        assert_eq!(users[0].margin_mode("ETH_USDC"), MarginMode::Isolated);
+    }
+
+    #[tokio::test]
+    async fn test_cross_margin_liquidation_uses_account_equity() {
+        let mut engine = Engine::new();
+        engine
+            .set_margin_mode(&margin_mode("1", "SOL_USDC", MarginMode::Cross))
+            .await
+            .unwrap();
+        liquidation_setup(&mut engine, dec!(15.5)).await;
+        {
+            let mut users = engine.users.write().await;
+            users[0].balances[0].balance = dec!(25);
+        }
+
+        // Isolated would be liquidated, but the free balance backs the account
+        assert!(engine
+            .pnl_service
+            .liquidation_candidates("SOL_USDC", dec!(16.5))
+            .await
+            .is_empty());
+
+        engine.check_liquidations("SOL_USDC", dec!(15.5)).await;
+
+        // The 22.5 loss is paid from collateral, then free balance
+        let users = engine.users.read().await;
+        assert!(users[0].margin_positions.is_empty());
+        assert_eq!(users[0].balances[0].balance, dec!(2.345));
+        assert_eq!(users[0].balances[0].locked_balance, dec!(0));
+        let fund = users
+            .iter()
+            .find(|u| u.id == INSURANCE_FUND_ACCOUNT)
+            .unwrap();
+        assert!(fund.balances.iter().all(|b| b.balance.is_zero()));
+    }
+
+    #[tokio::test]
+    async fn test_close_position_realizes_pnl_in_parts() {
+        let mut engine = Engine::new();
+        liquidation_setup(&mut engine, dec!(22)).await;
+
+        let close = |position_type: OrderType, quantity: Option<Decimal>| ClosePositionPayload {
+            user_id: "1".to_string(),
+            market: "SOL_USDC".to_string(),
+            position_type,
+            quantity,
+        };
+
+        assert!(engine
+            .close_position(&close(OrderType::MarginShort, None))
+            .await
+            .is_err());
+        assert!(engine
+            .close_position(&close(OrderType::MarginLong, Some(dec!(6))))
+            .await
+            .is_err());
+
+        let placed = engine
+            .close_position(&close(OrderType::MarginLong, Some(dec!(2))))
+            .await
+            .unwrap();
+        assert_eq!(placed.filled_qty, dec!(2));
+        {
+            let users = engine.users.read().await;
+            let position = &users[0].margin_positions[0];
+            assert_eq!(position.size, dec!(3));
+            assert_eq!(position.collateral, dec!(12));
+            assert_eq!(users[0].margin_used, dec!(12));
+            assert_eq!(users[0].realized_pnl, dec!(4));
+            assert_eq!(users[0].balances[0].locked_balance, dec!(12));
+        }
+
+        engine
+            .close_position(&close(OrderType::MarginLong, None))
+            .await
+            .unwrap();
+        let users = engine.users.read().await;
+        assert!(users[0].margin_positions.is_empty());
+        assert_eq!(users[0].margin_used, dec!(0));
+        assert_eq!(users[0].realized_pnl, dec!(10));
+        assert_eq!(users[0].balances[0].locked_balance, dec!(0));
+    }
+
+    #[tokio::test]
+    async fn test_close_position_against_spot_bid() {
+        let mut engine = Engine::new();
+        open_long(&mut engine).await;
+        engine
+            .create_order(&order(
+                "2",
+                OrderSide::Buy,
+                OrderType::Spot,
+                dec!(22),
+                dec!(5),
+            ))
+            .await
+            .unwrap();
+
+        let placed = engine
+            .close_position(&ClosePositionPayload {
+                user_id: "1".to_string(),
+                market: "SOL_USDC".to_string(),
+                position_type: OrderType::MarginLong,
+                quantity: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(placed.filled_qty, dec!(5));
+
+        let users = engine.users.read().await;
+        let user = users.iter().find(|u| u.id == "1").unwrap();
+        assert!(user.margin_positions.is_empty());
+        assert_eq!(user.realized_pnl, dec!(10));
+        let sol = user.balances.iter().find(|b| b.ticker == "SOL").unwrap();
+        assert_eq!((sol.balance, sol.locked_balance), (dec!(100), dec!(0)));
+
+        let bidder = users.iter().find(|u| u.id == "2").unwrap();
+        let usdc = bidder.balances.iter().find(|b| b.ticker == "USDC").unwrap();
+        assert_eq!((usdc.balance, usdc.locked_balance), (dec!(9890), dec!(0)));
+    }
+
+    #[tokio::test]
+    async fn test_adjust_collateral_checks_leverage_and_maintenance() {
+        let mut engine = Engine::new();
+        liquidation_setup(&mut engine, dec!(16.5)).await;
+        engine
+            .price_service
+            .update_price(
+                "SOL_USDC",
+                PriceInfo {
+                    last_trade_price: None,
+                    mark_price: dec!(17),
+                    index_price: None,
+                    timestamp: Utc::now().timestamp(),
+                },
+            )
+            .await;
+
+        let adjust = |amount: Decimal| AdjustCollateralPayload {
+            user_id: "1".to_string(),
+            market: "SOL_USDC".to_string(),
+            amount,
+        };
+
+        // Equity of 19 - 15 at a mark of 17 is below the 4.25 maintenance margin
+        let err = engine
+            .adjust_collateral(&adjust(dec!(1)), dec!(-1))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("maintenance margin"));
+        assert!(engine
+            .adjust_collateral(&adjust(dec!(20000)), dec!(20000))
+            .await
+            .is_err());
+
+        let adjusted = engine
+            .adjust_collateral(&adjust(dec!(5)), dec!(5))
+            .await
+            .unwrap();
+        assert_eq!(adjusted.position.collateral, dec!(25));
+        assert_eq!(adjusted.position.leverage, dec!(4));
+        let liquidation_price = adjusted.liquidation_price;
+        assert!(adjusted
+            .position
+            .is_liquidatable(liquidation_price - dec!(0.01), dec!(0.05)));
+        assert!(!adjusted
+            .position
+            .is_liquidatable(liquidation_price + dec!(0.01), dec!(0.05)));
+        {
+            let users = engine.users.read().await;
+            assert_eq!(users[0].margin_positions[0].collateral, dec!(25));
+            assert_eq!(users[0].margin_used, dec!(25));
+            assert_eq!(users[0].balances[0].locked_balance, dec!(25));
+        }
+
+        let adjusted = engine
+            .adjust_collateral(&adjust(dec!(2)), dec!(-2))
+            .await
+            .unwrap();
+        assert_eq!(adjusted.position.collateral, dec!(23));
+        assert_eq!(balance(&engine, "1", "USDC").await, dec!(10000));
+        let users = engine.users.read().await;
+        assert_eq!(users[0].balances[0].locked_balance, dec!(23));
+    }
+
+    #[tokio::test]
+    async fn test_adjust_collateral_rejects_full_withdrawal_and_cross() {
+        let mut engine = Engine::new();
+        open_long(&mut engine).await;
+
+        let adjust = AdjustCollateralPayload {
+            user_id: "1".to_string(),
+            market: "SOL_USDC".to_string(),
+            amount: dec!(20),
+        };
+        let err = engine
+            .adjust_collateral(&adjust, dec!(-20))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("without collateral"), "{}", err);
+        {
+            let users = engine.users.read().await;
+            assert_eq!(users[0].margin_positions[0].collateral, dec!(20));
+            assert_eq!(users[0].balances[0].locked_balance, dec!(20));
+        }
+
+        let mut engine = Engine::new();
+        engine
+            .set_margin_mode(&margin_mode("1", "SOL_USDC", MarginMode::Cross))
+            .await
+            .unwrap();
+        open_long(&mut engine).await;
+        let err = engine
+            .adjust_collateral(&adjust, dec!(5))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("isolated"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_pnl_monitor_marks_liquidation_price_and_margin_ratio() {
+        let mut engine = Engine::new();
+        engine
+            .set_margin_mode(&margin_mode("2", "SOL_USDC", MarginMode::Cross))
+            .await
+            .unwrap();
+        open_position(
+            &mut engine,
+            "1",
+            PositionType::Long,
+            dec!(20),
+            dec!(5),
+            dec!(20),
+        )
+        .await;
+        open_position(
+            &mut engine,
+            "2",
+            PositionType::Short,
+            dec!(20),
+            dec!(5),
+            dec!(20),
+        )
+        .await;
+        engine
+            .price_service
+            .update_price(
+                "SOL_USDC",
+                PriceInfo {
+                    last_trade_price: None,
+                    mark_price: dec!(17),
+                    index_price: None,
+                    timestamp: Utc::now().timestamp(),
+                },
+            )
+            .await;
+
+        let positions = engine.pnl_service.check_positions().await;
+        assert_eq!(positions.len(), 2);
+
+        // Isolated: liquidated once 20 + (p - 20) * 5 falls to 5% of 5p
+        let isolated = &positions["1"][0];
+        assert_eq!(isolated.unrealized_pnl, dec!(-15));
+        assert_eq!(
+            isolated.liquidation_price.unwrap().round_dp(4),
+            dec!(16.8421)
+        );
+        assert_eq!(isolated.margin_ratio.unwrap().round_dp(4), dec!(0.0588));
+
+        // Cross: the 9980 of free balance backs the short as well
+        let cross = &positions["2"][0];
+        assert_eq!(cross.unrealized_pnl, dec!(15));
+        assert_eq!(cross.adl_rank, Some(1));
+        assert_eq!(
+            cross.liquidation_price.unwrap().round_dp(4),
+            dec!(1923.8095)
+        );
+        assert_eq!(cross.margin_ratio.unwrap().round_dp(4), dec!(117.8235));
+
+        let users = engine.users.read().await;
+        assert_eq!(users[0].margin_positions[0], *isolated);
+    }
+
+    #[tokio::test]
+    async fn test_account_summary_values_balances_and_margin() {
+        let mut engine = Engine::new();
+        open_position(
+            &mut engine,
+            "1",
+            PositionType::Long,
+            dec!(20),
+            dec!(5),
+            dec!(20),
+        )
+        .await;
+        engine
+            .price_service
+            .update_price(
+                "SOL_USDC",
+                PriceInfo {
+                    last_trade_price: None,
+                    mark_price: dec!(17),
+                    index_price: None,
+                    timestamp: Utc::now().timestamp(),
+                },
+            )
+            .await;
+
+        let account = engine.pnl_service.account_summary("1").await.unwrap();
+
+        // 10000 USDC and 100 SOL at 17, BTC and ETH have no price yet
+        let values: Vec<Option<Decimal>> = account.balances.iter().map(|b| b.value).collect();
+        assert_eq!(
+            values,
+            vec![Some(dec!(10000)), Some(dec!(1700)), None, None]
+        );
+        assert_eq!(account.balance_value, dec!(11700));
+        assert_eq!(account.unrealized_pnl, dec!(-15));
+        assert_eq!(account.equity, dec!(11685));
+        assert_eq!(account.position_value, dec!(85));
+        assert_eq!(account.initial_margin, dec!(17));
+        assert_eq!(account.maintenance_margin, dec!(4.25));
+        assert_eq!(account.free_collateral, dec!(9980));
+        assert_eq!(account.leverage.unwrap().round_dp(4), dec!(0.0073));
+
+        assert!(engine.pnl_service.account_summary("9").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_margin_calls_fire_once_per_threshold_crossing() {
+        let mut engine = Engine::new();
+        open_position(
+            &mut engine,
+            "1",
+            PositionType::Long,
+            dec!(20),
+            dec!(5),
+            dec!(20),
+        )
+        .await;
+
+        // Margin calls below 17.78 and 17.14, liquidation below 16.84
+        let mut recorded = Vec::new();
+        for mark_price in [
+            dec!(18),
+            dec!(17.5),
+            dec!(17.5),
+            dec!(17),
+            dec!(17),
+            dec!(18),
+            dec!(17),
+        ] {
+            engine
+                .price_service
+                .update_price(
+                    "SOL_USDC",
+                    PriceInfo {
+                        last_trade_price: None,
+                        mark_price,
+                        index_price: None,
+                        timestamp: Utc::now().timestamp(),
+                    },
+                )
+                .await;
+            engine.pnl_service.check_positions().await;
+
+            let users = engine.users.read().await;
+            let thresholds: Vec<Decimal> =
+                users[0].margin_calls.iter().map(|c| c.threshold).collect();
+            recorded.push(thresholds.len());
+            if mark_price == dec!(17.5) {
+                assert_eq!(thresholds, vec![dec!(0.5)]);
+            }
+        }
+        assert_eq!(recorded, vec![0, 1, 1, 2, 2, 2, 3]);
+
+        let account = engine.pnl_service.account_summary("1").await.unwrap();
+        let last = account.margin_calls.last().unwrap();
+        assert_eq!(last.threshold, dec!(0.75));
+        assert_eq!(last.market, "SOL_USDC");
+        assert_eq!(last.position_type, PositionType::Long);
+        assert_eq!(last.equity, dec!(5));
+        assert_eq!(last.maintenance_margin, dec!(4.25));
+        assert_eq!(last.mark_price, dec!(17));
+    }
 }
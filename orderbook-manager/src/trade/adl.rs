@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::models::{MarginPosition, PositionType, User};
+
+/// Profitable `position_type` positions of `market`, first reduced first.
+pub fn adl_queue<'a>(
+    users: &'a [User],
+    market: &str,
+    position_type: &PositionType,
+    mark_price: Decimal,
+) -> Vec<&'a MarginPosition> {
+    let mut queue: Vec<(Decimal, &MarginPosition)> = users
+        .iter()
+        .flat_map(|user| user.margin_positions.iter())
+        .filter(|p| p.asset == market && &p.position_type == position_type)
+        .filter_map(|p| Some((p.adl_score(mark_price)?, p)))
+        .collect();
+    queue.sort_by(|(a, _), (b, _)| b.cmp(a));
+    queue.into_iter().map(|(_, position)| position).collect()
+}
+
+/// Sets the `adl_rank` of every position in `market` from both sides' queues.
+pub fn rank_adl_queue(users: &mut [User], market: &str, mark_price: Decimal) {
+    let mut ranks: HashMap<(String, PositionType), u32> = HashMap::new();
+    for position_type in [PositionType::Long, PositionType::Short] {
+        for (rank, position) in adl_queue(users, market, &position_type, mark_price)
+            .into_iter()
+            .enumerate()
+        {
+            ranks.insert(
+                (position.user_id.clone(), position_type.clone()),
+                rank as u32 + 1,
+            );
+        }
+    }
+
+    for position in users
+        .iter_mut()
+        .flat_map(|user| user.margin_positions.iter_mut())
+        .filter(|p| p.asset == market)
+    {
+        position.adl_rank = ranks
+            .get(&(position.user_id.clone(), position.position_type.clone()))
+            .copied();
+    }
+}
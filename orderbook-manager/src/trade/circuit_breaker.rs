@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+
+use crate::models::PriceBand;
+
+/// Recent trade prices of a market.
+pub struct CircuitBreaker {
+    recent_trades: VecDeque<(i64, Decimal)>,
+    pub halted_until: Option<i64>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        CircuitBreaker {
+            recent_trades: VecDeque::new(),
+            halted_until: None,
+        }
+    }
+
+    pub fn last_price(&self) -> Option<Decimal> {
+        self.recent_trades.back().map(|(_, price)| *price)
+    }
+
+    pub fn record_trade(&mut self, price: Decimal, timestamp: i64) {
+        self.recent_trades.push_back((timestamp, price));
+    }
+
+    /// Forgets every trade but the last, which stays the band's reference.
+    pub fn reset_window(&mut self) {
+        let keep_from = self.recent_trades.len().saturating_sub(1);
+        self.recent_trades.drain(..keep_from);
+    }
+
+    /// The price move within the window, when past the halt threshold.
+    pub fn price_move(&mut self, band: &PriceBand, now: i64) -> Option<Decimal> {
+        while self.recent_trades.len() > 1
+            && self
+                .recent_trades
+                .front()
+                .is_some_and(|(timestamp, _)| *timestamp < now - band.window_secs)
+        {
+            self.recent_trades.pop_front();
+        }
+
+        let prices = self.recent_trades.iter().map(|(_, price)| *price);
+        let low = prices.clone().min()?;
+        let high = prices.max()?;
+        if low <= Decimal::ZERO {
+            return None;
+        }
+
+        let price_move = (high - low) / low;
+        (price_move > band.halt_move_pct).then_some(price_move)
+    }
+}
@@ -1,32 +1,46 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
 use chrono::Utc;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use serde_json::{self, json};
 use tokio::sync::{Mutex, RwLock};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::{
+    constants::{EXCHANGE_FEE_ACCOUNT, INSURANCE_FUND_ACCOUNT},
     models::{
-        AddTradePayload, Balance, CancelOrderPayload, CreateOrderPayload, MarginPositionsPayload, MessageFromApi, MessageToApi, OpenOrdersPayload, Order, OrderCancelledPayload, OrderPlacedPayload, OrderSide, OrderType, PositionType, TradeData, User, UserBalancesPayload
+        AddTradePayload, AdjustCollateralPayload, AdlEvent, AmendOrderPayload, Balance,
+        CancelAllOrdersPayload, CancelOrderPayload, ClosePositionPayload,
+        CollateralAdjustedPayload, CreateMarketPayload, CreateOrderPayload, ErrorPayload,
+        FundingHistoryPayload, FundingRate, Iceberg, LiquidationConfig, LiquidationEvent,
+        MarginMode, MarginModePayload, MarginPosition, MarginPositionsPayload, MarketSpec,
+        MarketStatus, MarketStatusPayload, MarketsPayload, MessageFromApi, MessageToApi,
+        OpenOrdersPayload, Order, OrderAmendedPayload, OrderCancelledPayload, OrderPlacedPayload,
+        OrderSide, OrderType, OrdersCancelledPayload, PositionType, SetMarginModePayload,
+        SetMarketStatusPayload, TimeInForce, TradeData, TriggerRejectedEvent, User,
+        UserBalancesPayload,
     },
     services::{
-        pnl_service::PnlService,
-        price_service::{PriceInfo, PriceService},
+        market_registry::MarketRegistry, pnl_service::PnlService, price_service::PriceService,
         redis_manager::RedisManager,
     },
 };
 
-use super::Orderbook;
+use super::{adl_queue, Orderbook};
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct Engine {
     pub orderbooks: Arc<Mutex<HashMap<String, Arc<Mutex<Orderbook>>>>>,
     pub users: Arc<RwLock<Vec<User>>>,
     pub price_service: Arc<PriceService>,
     pub pnl_service: Arc<PnlService>,
+    pub market_registry: Arc<MarketRegistry>,
 }
 
 impl Engine {
@@ -62,6 +76,10 @@ impl Engine {
             max_leverage: dec!(10),
             margin_used: dec!(0),
             realized_pnl: dec!(0),
+            daily_volume: BTreeMap::new(),
+            margin_modes: HashMap::new(),
+            margin_calls: Vec::new(),
+            rejected_triggers: Vec::new(),
         });
         initial_users.push(User {
             id: "2".to_string(),
@@ -92,62 +110,105 @@ impl Engine {
             max_leverage: dec!(10),
             margin_used: dec!(0),
             realized_pnl: dec!(0),
+            daily_volume: BTreeMap::new(),
+            margin_modes: HashMap::new(),
+            margin_calls: Vec::new(),
+            rejected_triggers: Vec::new(),
         });
 
+        for account in [EXCHANGE_FEE_ACCOUNT, INSURANCE_FUND_ACCOUNT] {
+            initial_users.push(User {
+                margin_enabled: false,
+                ..User::_new(account.to_string())
+            });
+        }
+
         let users = Arc::new(RwLock::new(initial_users));
         let price_service = Arc::new(PriceService::new());
 
-        let pnl_service = Arc::new(PnlService::new(users.clone(), price_service.clone()));
-
-        pnl_service.start_monitoring();
-
+        let market_registry = Arc::new(MarketRegistry::from_env());
         let mut orderbooks = HashMap::new();
-        let markets = vec![("SOL", "USDC"), ("BTC", "USDC"), ("ETH", "USDC")];
-
-        for (base, quote) in &markets {
-            let market = format!("{}_{}", base, quote);
-            let orderbook = Arc::new(Mutex::new(Orderbook::new(
-                base.to_string(),
-                quote.to_string(),
-            )));
-
-            let ob_clone = orderbook.clone();
-            let price_service_clone = price_service.clone();
-            let market_clone = market.clone();
-
-            std::thread::Builder::new()
-                .name(format!("{}_orderbook", market))
-                .spawn(move || {
-                    let rt = tokio::runtime::Builder::new_current_thread()
-                        .enable_all()
-                        .build()
-                        .unwrap();
-
-                    rt.block_on(async move {
-                        let mut interval = tokio::time::interval(Duration::from_secs(1));
-
-                        loop {
-                            interval.tick().await;
-                            let ob = ob_clone.lock().await;
-                            if let Some(price_info) = ob.get_price_info().await {
-                                price_service_clone
-                                    .update_price(&market_clone, price_info)
-                                    .await;
-                            }
-                        }
-                    });
-                })
-                .expect(&format!("Failed to spawn thread for {}", market));
+
+        for spec in market_registry.load() {
+            let market = spec.market.clone();
+            let orderbook = Arc::new(Mutex::new(Orderbook::new(spec)));
 
             orderbooks.insert(market, orderbook);
         }
+        let shared_orderbooks = Arc::new(Mutex::new(orderbooks.clone()));
+
+        let pnl_service = Arc::new(PnlService::new(
+            users.clone(),
+            price_service.clone(),
+            shared_orderbooks.clone(),
+        ));
+
+        pnl_service.start_monitoring();
 
-        Engine {
-            orderbooks: Arc::new(Mutex::new(orderbooks)),
+        let engine = Engine {
+            orderbooks: shared_orderbooks,
             users,
             price_service,
             pnl_service,
+            market_registry,
+        };
+
+        for (market, orderbook) in orderbooks {
+            engine.spawn_price_thread(market, orderbook);
         }
+
+        engine
+    }
+
+    fn spawn_price_thread(&self, market: String, orderbook: Arc<Mutex<Orderbook>>) {
+        let engine = self.clone();
+        let thread_name = format!("{}_orderbook", market);
+
+        std::thread::Builder::new()
+            .name(thread_name.clone())
+            .spawn(move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+
+                rt.block_on(async move {
+                    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+                    loop {
+                        interval.tick().await;
+                        // Fetched before locking the orderbook, sources may do I/O
+                        let index_price = engine.price_service.index_price(&market);
+                        let price_info = {
+                            let mut ob = orderbook.lock().await;
+                            if ob.resume_after_cooldown(Utc::now().timestamp()) {
+                                info!(market = ?market, "Circuit breaker cooldown over, resuming trading");
+                                Self::publish_market_status(&market, ob.status, None);
+                            }
+
+                            ob.index_price = index_price;
+                            let price_info = ob.get_price_info().await;
+                            if let Some(price_info) = &price_info {
+                                ob.mark_price = Some(price_info.mark_price);
+                            }
+                            price_info
+                        };
+
+                        if let Some(price_info) = price_info {
+                            engine.price_service.update_price(&market, price_info).await;
+                            // Funding, triggers and liquidations run on the main loop
+                            let message = serde_json::json!({
+                                "client_id": "",
+                                "message": { "type": "PRICE_UPDATED", "market": market },
+                            });
+                            if let Err(e) = RedisManager::instance().push_to_engine(&message) {
+                                error!("Failed to queue price update for {}: {}", market, e);
+                            }
+                        }
+                    }
+                });
+            })
+            .unwrap_or_else(|_| panic!("Failed to spawn thread {}", thread_name));
     }
 
     pub async fn process(&mut self, client_id: String, message: MessageFromApi) {
@@ -156,38 +217,24 @@ impl Engine {
                 let result = self.create_order(&data).await;
 
                 match result {
-                    Ok((remaining_qty, filled_qty, order_id)) => {
+                    Ok(placed) => {
                         info!(
-                            order_id,
-                            ?remaining_qty,
-                            ?filled_qty,
+                            order_id = placed.order_id,
+                            remaining_qty = ?placed.remaining_qty,
+                            filled_qty = ?placed.filled_qty,
                             "Order created successfully"
                         );
                         let redis_manager = RedisManager::instance();
-                        let message = MessageToApi::OrderPlaced {
-                            payload: OrderPlacedPayload {
-                                order_id,
-                                remaining_qty,
-                                filled_qty,
-                            },
-                        };
+                        let message = MessageToApi::OrderPlaced { payload: placed };
 
                         let _ = redis_manager.send_to_api(&client_id, &message);
-                        let trade_info = json!({
-                            "price": data.price,
-                            "quantity": filled_qty,
-                            "side": data.side,
-                            "timestamp": Utc::now().timestamp()
-                        });
-                        let _ = redis_manager
-                            .publish_message(&format!("trade@{}", data.market), &trade_info);
                     }
                     Err(e) => {
                         error!("Failed to create order: {}", e);
                         let redis_manager = RedisManager::instance();
                         let message = MessageToApi::OrderCancelled {
                             payload: OrderCancelledPayload {
-                                message: Some(String::from("Order execution failed")),
+                                message: Some(format!("Order execution failed: {}", e)),
                             },
                         };
 
@@ -195,6 +242,36 @@ impl Engine {
                     }
                 }
             }
+            MessageFromApi::ClosePosition { data } => {
+                let redis_manager = RedisManager::instance();
+                let message = match self.close_position(&data).await {
+                    Ok(placed) => {
+                        info!(
+                            order_id = placed.order_id,
+                            filled_qty = ?placed.filled_qty,
+                            "Position closed"
+                        );
+                        MessageToApi::OrderPlaced { payload: placed }
+                    }
+                    Err(e) => {
+                        error!("Failed to close position: {}", e);
+                        MessageToApi::OrderCancelled {
+                            payload: OrderCancelledPayload {
+                                message: Some(format!("Position close failed: {}", e)),
+                            },
+                        }
+                    }
+                };
+                let _ = redis_manager.send_to_api(&client_id, &message);
+            }
+            MessageFromApi::AddCollateral { data } => {
+                let result = self.adjust_collateral(&data, data.amount).await;
+                Self::send_collateral_adjusted(&client_id, result);
+            }
+            MessageFromApi::RemoveCollateral { data } => {
+                let result = self.adjust_collateral(&data, -data.amount).await;
+                Self::send_collateral_adjusted(&client_id, result);
+            }
             MessageFromApi::CancelOrder { data } => {
                 info!(?data, "Cancelling order");
                 let result = self.cancel_order(&data).await;
@@ -224,6 +301,62 @@ impl Engine {
                     }
                 }
             }
+            MessageFromApi::AmendOrder { data } => {
+                info!(?data, "Amending order");
+                let result = self.amend_order(&data).await;
+                let redis_manager = RedisManager::instance();
+
+                match result {
+                    Ok(order) => {
+                        info!(order_id = ?order.id, "Order amended successfully");
+                        let message = MessageToApi::OrderAmended {
+                            payload: OrderAmendedPayload {
+                                order_id: order.id,
+                                price: order.price,
+                                quantity: order.quantity,
+                            },
+                        };
+
+                        let _ = redis_manager.send_to_api(&client_id, &message);
+                    }
+                    Err(e) => {
+                        error!("Failed to amend order: {}", e);
+                        let message = MessageToApi::OrderCancelled {
+                            payload: OrderCancelledPayload {
+                                message: Some(format!("ORDER AMEND FAILED: {}", e)),
+                            },
+                        };
+
+                        let _ = redis_manager.send_to_api(&client_id, &message);
+                    }
+                }
+            }
+            MessageFromApi::CancelAllOrders { data } => {
+                info!(?data, "Cancelling all orders");
+                let result = self.cancel_all_orders(&data).await;
+                let redis_manager = RedisManager::instance();
+
+                match result {
+                    Ok(order_ids) => {
+                        info!(count = order_ids.len(), "Orders cancelled successfully");
+                        let message = MessageToApi::OrdersCancelled {
+                            payload: OrdersCancelledPayload { order_ids },
+                        };
+
+                        let _ = redis_manager.send_to_api(&client_id, &message);
+                    }
+                    Err(e) => {
+                        error!("Failed to cancel orders: {}", e);
+                        let message = MessageToApi::OrderCancelled {
+                            payload: OrderCancelledPayload {
+                                message: Some(String::from("ORDER CANCELLATION FAILED")),
+                            },
+                        };
+
+                        let _ = redis_manager.send_to_api(&client_id, &message);
+                    }
+                }
+            }
             MessageFromApi::GetQuote { data } => {
                 let orderbooks = self.orderbooks.lock().await;
                 let orderbook = orderbooks
@@ -270,6 +403,12 @@ impl Engine {
                     }
                 }
 
+                for trigger_order in orderbook.lock().await.trigger_orders.iter() {
+                    if trigger_order.user_id == data.user_id {
+                        open_orders.push(trigger_order.clone());
+                    }
+                }
+
                 let redis_manager = RedisManager::instance();
                 let message = MessageToApi::OpenOrders {
                     payload: OpenOrdersPayload { open_orders },
@@ -293,6 +432,19 @@ impl Engine {
 
                 let _ = redis_manager.send_to_api(&client_id, &message);
             }
+            MessageFromApi::GetAccount { data } => {
+                let redis_manager = RedisManager::instance();
+                let message = match self.pnl_service.account_summary(&data.user_id).await {
+                    Some(account) => MessageToApi::Account { payload: account },
+                    None => MessageToApi::Error {
+                        payload: ErrorPayload {
+                            message: format!("User {} not found", data.user_id),
+                        },
+                    },
+                };
+
+                let _ = redis_manager.send_to_api(&client_id, &message);
+            }
             MessageFromApi::GetMarginPositions { data } => {
                 let mut users = self.users.write().await;
                 info!(?data, "Getting margin positions for user {}", data.user_id);
@@ -311,47 +463,942 @@ impl Engine {
 
                 let _ = redis_manager.send_to_api(&client_id, &message);
             }
+            MessageFromApi::CreateMarket { data } => {
+                info!(?data, "Creating market");
+                let result = self.create_market(&data).await;
+                let redis_manager = RedisManager::instance();
+
+                match result {
+                    Ok(spec) => {
+                        info!(market = ?spec.market, "Market created successfully");
+                        let message = MessageToApi::MarketCreated { payload: spec };
+                        let _ = redis_manager.send_to_api(&client_id, &message);
+                    }
+                    Err(e) => {
+                        error!("Failed to create market: {}", e);
+                        let message = MessageToApi::Error {
+                            payload: ErrorPayload {
+                                message: format!("MARKET CREATION FAILED: {}", e),
+                            },
+                        };
+                        let _ = redis_manager.send_to_api(&client_id, &message);
+                    }
+                }
+            }
+            MessageFromApi::SetMarketStatus { data } => {
+                info!(?data, "Setting market status");
+                let result = self.set_market_status(&data).await;
+                let redis_manager = RedisManager::instance();
+
+                let message = match result {
+                    Ok(status) => MessageToApi::MarketStatus {
+                        payload: MarketStatusPayload {
+                            market: data.market,
+                            status,
+                            reason: None,
+                        },
+                    },
+                    Err(e) => {
+                        error!("Failed to set market status: {}", e);
+                        MessageToApi::Error {
+                            payload: ErrorPayload {
+                                message: format!("MARKET STATUS CHANGE FAILED: {}", e),
+                            },
+                        }
+                    }
+                };
+                let _ = redis_manager.send_to_api(&client_id, &message);
+            }
+            MessageFromApi::SetMarginMode { data } => {
+                let result = self.set_margin_mode(&data).await;
+                let redis_manager = RedisManager::instance();
+
+                let message = match result {
+                    Ok(mode) => MessageToApi::MarginMode {
+                        payload: MarginModePayload {
+                            user_id: data.user_id,
+                            market: data.market,
+                            mode,
+                        },
+                    },
+                    Err(e) => {
+                        error!("Failed to set margin mode: {}", e);
+                        MessageToApi::Error {
+                            payload: ErrorPayload {
+                                message: format!("MARGIN MODE CHANGE FAILED: {}", e),
+                            },
+                        }
+                    }
+                };
+                let _ = redis_manager.send_to_api(&client_id, &message);
+            }
+            MessageFromApi::GetMarkets => {
+                let markets = self.market_specs().await;
+
+                let redis_manager = RedisManager::instance();
+                let message = MessageToApi::Markets {
+                    payload: MarketsPayload { markets },
+                };
+                let _ = redis_manager.send_to_api(&client_id, &message);
+            }
             MessageFromApi::GetTicker { market } => {
                 let orderbooks = self.orderbooks.lock().await;
                 let orderbook = orderbooks.get(&market).ok_or("Market not found").unwrap();
                 let orderbook = orderbook.lock().await;
-                let price = orderbook.get_price_info().await;
+                let ticker = orderbook.ticker().await;
 
                 let redis_manager = RedisManager::instance();
-                let message = MessageToApi::TickerPrice {
-                    market,
-                    price: price.map(|p| PriceInfo {
-                        last_trade_price: p.last_trade_price,
-                        mark_price: p.mark_price,
-                        index_price: p.index_price,
-                        timestamp: p.timestamp,
+                let message = MessageToApi::TickerPrice { payload: ticker };
+                let _ = redis_manager.send_to_api(&client_id, &message);
+            }
+            MessageFromApi::PriceUpdated { market } => {
+                self.on_price_update(&market).await;
+            }
+            MessageFromApi::GetFundingHistory { market } => {
+                let orderbook = self.orderbooks.lock().await.get(&market).cloned();
+
+                let message = match orderbook {
+                    Some(orderbook) => MessageToApi::FundingHistory {
+                        payload: FundingHistoryPayload {
+                            market,
+                            history: orderbook
+                                .lock()
+                                .await
+                                .funding
+                                .history
+                                .iter()
+                                .cloned()
+                                .collect(),
+                        },
+                    },
+                    None => MessageToApi::Error {
+                        payload: ErrorPayload {
+                            message: format!("Market {} not found", market),
+                        },
+                    },
+                };
+                let _ = RedisManager::instance().send_to_api(&client_id, &message);
+            }
+        }
+    }
+
+    /// Settles funding, fires triggers and runs liquidations at the mark price.
+    pub async fn on_price_update(&mut self, market: &str) {
+        let Some(orderbook) = self.orderbooks.lock().await.get(market).cloned() else {
+            return;
+        };
+        let (triggered_orders, funding_rate, liquidation_mark) = {
+            let mut ob = orderbook.lock().await;
+            let Some(mark_price) = ob.mark_price else {
+                return;
+            };
+            let funding_rate = ob.accrue_funding(mark_price, Utc::now().timestamp());
+            if ob.status == MarketStatus::Trading {
+                let triggered_orders = ob.take_triggered_orders(mark_price);
+                (triggered_orders, funding_rate, Some(mark_price))
+            } else {
+                (Vec::new(), funding_rate, None)
+            }
+        };
+
+        if let Some(funding_rate) = funding_rate {
+            self.settle_funding(&funding_rate).await;
+        }
+
+        for order in triggered_orders {
+            self.execute_trigger_order(market, order).await;
+        }
+
+        if let Some(mark_price) = liquidation_mark {
+            self.check_liquidations(market, mark_price).await;
+        }
+    }
+
+    pub async fn create_market(
+        &mut self,
+        payload: &CreateMarketPayload,
+    ) -> Result<MarketSpec, Box<dyn std::error::Error>> {
+        if !self.market_registry.is_persistent() {
+            return Err(
+                "Market registry not configured, set MARKETS_CONFIG to create markets".into(),
+            );
+        }
+
+        let base_asset = payload.base_asset.to_uppercase();
+        let quote_asset = payload.quote_asset.to_uppercase();
+        let valid_asset =
+            |asset: &str| !asset.is_empty() && asset.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid_asset(&base_asset) || !valid_asset(&quote_asset) {
+            return Err("Assets must be non-empty and alphanumeric".into());
+        }
+
+        let defaults = MarketSpec::new(&base_asset, &quote_asset);
+        let spec = MarketSpec {
+            tick_size: payload.tick_size.unwrap_or(defaults.tick_size),
+            lot_size: payload.lot_size.unwrap_or(defaults.lot_size),
+            min_quantity: payload.min_quantity.unwrap_or(defaults.min_quantity),
+            min_notional: payload.min_notional.unwrap_or(defaults.min_notional),
+            max_order_size: payload.max_order_size.unwrap_or(defaults.max_order_size),
+            product: payload.product.unwrap_or(defaults.product),
+            self_trade_prevention: payload
+                .self_trade_prevention
+                .unwrap_or(defaults.self_trade_prevention),
+            ..defaults
+        };
+        if [spec.tick_size, spec.lot_size, spec.max_order_size]
+            .iter()
+            .any(|value| *value <= Decimal::ZERO)
+        {
+            return Err("Tick size, lot size and max order size must be positive".into());
+        }
+
+        let orderbook = {
+            let mut orderbooks = self.orderbooks.lock().await;
+            if orderbooks.contains_key(&spec.market) {
+                return Err("Market already exists".into());
+            }
+
+            let orderbook = Arc::new(Mutex::new(Orderbook::new(spec.clone())));
+            orderbooks.insert(spec.market.clone(), orderbook.clone());
+            orderbook
+        };
+
+        self.spawn_price_thread(spec.market.clone(), orderbook);
+        self.market_registry.save(&self.market_specs().await);
+
+        Ok(spec)
+    }
+
+    pub async fn set_market_status(
+        &mut self,
+        payload: &SetMarketStatusPayload,
+    ) -> Result<MarketStatus, Box<dyn std::error::Error>> {
+        let orderbooks = self.orderbooks.lock().await;
+        let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+        let mut orderbook_guard = orderbook.lock().await;
+
+        if orderbook_guard.status == MarketStatus::Delisted {
+            return Err("Delisted markets cannot be reopened".into());
+        }
+        orderbook_guard.status = payload.status;
+
+        if payload.status == MarketStatus::Delisted {
+            let removed = orderbook_guard.remove_all_orders();
+            let mut users = self.users.write().await;
+
+            for order in removed.iter().filter(|o| o.trigger_price.is_none()) {
+                let (ticker, locked_amount) = Self::locked_for_order(&payload.market, order);
+                if let Some(balance) = users
+                    .iter_mut()
+                    .find(|u| u.id == order.user_id)
+                    .and_then(|u| u.balances.iter_mut().find(|b| b.ticker == ticker))
+                {
+                    balance.locked_balance -= locked_amount;
+                }
+            }
+            info!(market = ?payload.market, count = removed.len(), "Cancelled orders of delisted market");
+        }
+
+        orderbook_guard.circuit_breaker.halted_until = None;
+
+        Self::publish_market_status(&payload.market, payload.status, None);
+        if payload.status == MarketStatus::Delisted {
+            let redis_manager = RedisManager::instance();
+            let _ = redis_manager.publish_message(
+                &format!("depth@{}", payload.market),
+                &serde_json::to_value(orderbook_guard.get_depth()).unwrap(),
+            );
+        }
+
+        warn!(market = ?payload.market, status = ?payload.status, "Market status changed");
+        Ok(payload.status)
+    }
+
+    /// Closes a margin position, or `quantity` of it, with a reduce-only order.
+    pub async fn close_position(
+        &mut self,
+        payload: &ClosePositionPayload,
+    ) -> Result<OrderPlacedPayload, Box<dyn std::error::Error>> {
+        let position_type = match payload.position_type {
+            OrderType::MarginLong => PositionType::Long,
+            OrderType::MarginShort => PositionType::Short,
+            _ => return Err("Only MarginLong and MarginShort positions can be closed".into()),
+        };
+        let position = {
+            let users = self.users.read().await;
+            users
+                .iter()
+                .find(|u| u.id == payload.user_id)
+                .and_then(|user| {
+                    user.margin_positions
+                        .iter()
+                        .find(|p| p.asset == payload.market && p.position_type == position_type)
+                })
+                .cloned()
+                .ok_or("Position not found")?
+        };
+
+        let quantity = payload.quantity.unwrap_or(position.size);
+        self.create_order(&Self::closing_order(&position, quantity))
+            .await
+    }
+
+    /// A reduce-only market order closing `quantity` of `position`.
+    fn closing_order(position: &MarginPosition, quantity: Decimal) -> CreateOrderPayload {
+        CreateOrderPayload {
+            user_id: position.user_id.clone(),
+            market: position.asset.clone(),
+            price: Decimal::ZERO,
+            quantity,
+            side: match position.position_type {
+                PositionType::Long => OrderSide::Sell,
+                PositionType::Short => OrderSide::Buy,
+            },
+            order_type: OrderType::Market,
+            is_margin: true,
+            leverage: Some(position.leverage),
+            time_in_force: TimeInForce::Ioc,
+            trigger_price: None,
+            display_quantity: None,
+            self_trade_prevention: None,
+            reduce_only: true,
+        }
+    }
+
+    /// Moves `delta` into or out of an isolated position's collateral.
+    pub async fn adjust_collateral(
+        &self,
+        payload: &AdjustCollateralPayload,
+        delta: Decimal,
+    ) -> Result<CollateralAdjustedPayload, Box<dyn std::error::Error>> {
+        if payload.amount <= Decimal::ZERO {
+            return Err("Amount must be positive".into());
+        }
+        let maintenance_margin_rate = {
+            let orderbooks = self.orderbooks.lock().await;
+            let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+            let rate = orderbook
+                .lock()
+                .await
+                .spec
+                .liquidation
+                .maintenance_margin_rate;
+            rate
+        };
+        let quote_asset = payload.market.split('_').nth(1).unwrap_or("USDC");
+
+        let mut users = self.users.write().await;
+        let user = users
+            .iter_mut()
+            .find(|u| u.id == payload.user_id)
+            .ok_or("User not found")?;
+        if user.margin_mode(&payload.market) == MarginMode::Cross {
+            return Err("Collateral can only be adjusted on isolated positions".into());
+        }
+        if delta > user.free_balance(quote_asset) {
+            return Err(format!("Insufficient {} balance", quote_asset).into());
+        }
+        let max_leverage = user.max_leverage;
+        let position = user
+            .margin_positions
+            .iter_mut()
+            .find(|p| p.asset == payload.market)
+            .ok_or("Position not found")?;
+
+        let mut adjusted = position.clone();
+        adjusted.collateral += delta;
+        if adjusted.collateral <= Decimal::ZERO {
+            return Err("Withdrawal would leave the position without collateral".into());
+        }
+        adjusted.leverage = adjusted.entry_price * adjusted.size / adjusted.collateral;
+        if delta < Decimal::ZERO {
+            let mark_price = self
+                .price_service
+                .get_price(&payload.market)
+                .await
+                .unwrap_or(position.entry_price);
+            if adjusted.leverage > max_leverage {
+                return Err(format!(
+                    "Withdrawal would exceed the maximum leverage of {}",
+                    max_leverage
+                )
+                .into());
+            }
+            if adjusted.is_liquidatable(mark_price, maintenance_margin_rate) {
+                return Err("Withdrawal would breach the maintenance margin".into());
+            }
+        }
+        let liquidation_price = adjusted.isolated_liquidation_price(maintenance_margin_rate);
+        adjusted.liquidation_price = Some(liquidation_price);
+        *position = adjusted.clone();
+
+        user.margin_used += delta;
+        if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == quote_asset) {
+            balance.locked_balance += delta;
+        }
+        info!(
+            user_id = ?payload.user_id,
+            market = ?payload.market,
+            ?delta,
+            collateral = ?adjusted.collateral,
+            "Adjusted position collateral"
+        );
+
+        Ok(CollateralAdjustedPayload {
+            liquidation_price,
+            position: adjusted,
+        })
+    }
+
+    fn send_collateral_adjusted(
+        client_id: &str,
+        result: Result<CollateralAdjustedPayload, Box<dyn std::error::Error>>,
+    ) {
+        let message = match result {
+            Ok(payload) => MessageToApi::CollateralAdjusted { payload },
+            Err(e) => {
+                error!("Failed to adjust collateral: {}", e);
+                MessageToApi::Error {
+                    payload: ErrorPayload {
+                        message: format!("COLLATERAL ADJUSTMENT FAILED: {}", e),
+                    },
+                }
+            }
+        };
+        let _ = RedisManager::instance().send_to_api(client_id, &message);
+    }
+
+    /// Switches a market's margin mode, refused while the user has a position.
+    pub async fn set_margin_mode(
+        &self,
+        payload: &SetMarginModePayload,
+    ) -> Result<MarginMode, Box<dyn std::error::Error>> {
+        if !self.orderbooks.lock().await.contains_key(&payload.market) {
+            return Err("Market not found".into());
+        }
+
+        let mut users = self.users.write().await;
+        let user = users
+            .iter_mut()
+            .find(|u| u.id == payload.user_id)
+            .ok_or("User not found")?;
+        if user
+            .margin_positions
+            .iter()
+            .any(|p| p.asset == payload.market)
+        {
+            return Err(format!(
+                "Cannot change the margin mode of {} with an open position",
+                payload.market
+            )
+            .into());
+        }
+
+        user.margin_modes
+            .insert(payload.market.clone(), payload.mode);
+        info!(
+            user_id = ?payload.user_id,
+            market = ?payload.market,
+            mode = ?payload.mode,
+            "Margin mode changed"
+        );
+        Ok(payload.mode)
+    }
+
+    /// Liquidates the positions in `market` below maintenance at `mark_price`.
+    pub async fn check_liquidations(&mut self, market: &str, mark_price: Decimal) {
+        let Some(orderbook) = self.orderbooks.lock().await.get(market).cloned() else {
+            return;
+        };
+        let config = orderbook.lock().await.spec.liquidation.clone();
+
+        let candidates = self
+            .pnl_service
+            .liquidation_candidates(market, mark_price)
+            .await;
+
+        for (user_id, position_type) in candidates {
+            if let Err(e) = self
+                .liquidate_position(market, &user_id, position_type, mark_price, &config)
+                .await
+            {
+                warn!(?market, ?user_id, "Liquidation failed: {}", e);
+            }
+        }
+    }
+
+    /// Closes a liquidated position, falling back to the insurance fund and ADL.
+    async fn liquidate_position(
+        &mut self,
+        market: &str,
+        user_id: &str,
+        position_type: PositionType,
+        mark_price: Decimal,
+        config: &LiquidationConfig,
+    ) -> Result<LiquidationEvent, Box<dyn std::error::Error>> {
+        let quote_asset = market.split('_').nth(1).unwrap_or("USDC");
+        let (position, margin_mode, backing, fund_balance) = {
+            let users = self.users.read().await;
+            let user = users
+                .iter()
+                .find(|u| u.id == user_id)
+                .ok_or("User not found")?;
+            let position = user
+                .margin_positions
+                .iter()
+                .find(|p| p.asset == market && p.position_type == position_type)
+                .cloned()
+                .ok_or("Position not found")?;
+            let margin_mode = user.margin_mode(market);
+            let backing = match margin_mode {
+                MarginMode::Isolated => Decimal::ZERO,
+                MarginMode::Cross => user.free_balance(quote_asset).max(Decimal::ZERO),
+            };
+            let fund_balance = users
+                .iter()
+                .find(|u| u.id == INSURANCE_FUND_ACCOUNT)
+                .and_then(|fund| fund.balances.iter().find(|b| b.ticker == quote_asset))
+                .map_or(Decimal::ZERO, |b| b.balance.max(Decimal::ZERO));
+            (position, margin_mode, backing, fund_balance)
+        };
+        // Backing before the insurance fund, which sets the bankruptcy price
+        let backed = MarginPosition {
+            collateral: position.collateral + backing,
+            ..position.clone()
+        };
+
+        let mut deleveraged_quantity = Decimal::ZERO;
+        if -backed.equity_at(mark_price) > fund_balance {
+            deleveraged_quantity = self
+                .auto_deleverage(market, &backed, position.size, mark_price)
+                .await;
+        }
+
+        let remaining = position.size - deleveraged_quantity;
+        let (filled_qty, filled_value) = if remaining.is_zero() {
+            (Decimal::ZERO, Decimal::ZERO)
+        } else {
+            let placed = self
+                .create_order(&Self::closing_order(&position, remaining))
+                .await?;
+            let filled_value: Decimal = placed
+                .fills
+                .iter()
+                .map(|fill| fill.price * fill.quantity)
+                .sum();
+            (placed.filled_qty, filled_value)
+        };
+
+        if filled_qty < remaining {
+            deleveraged_quantity += self
+                .auto_deleverage(market, &backed, remaining - filled_qty, mark_price)
+                .await;
+        }
+        if filled_qty.is_zero() && deleveraged_quantity.is_zero() {
+            return Err("No liquidity or ADL counterparty to close the position".into());
+        }
+
+        let entry_value = position.entry_price * filled_qty;
+        let realized_pnl = match position_type {
+            PositionType::Long => filled_value - entry_value,
+            PositionType::Short => entry_value - filled_value,
+        };
+        let equity = position.collateral * filled_qty / position.size + realized_pnl;
+        let fee = (filled_value * config.fee_rate).min(equity.max(Decimal::ZERO));
+
+        let (insurance_fund_draw, shortfall) = {
+            let mut users = self.users.write().await;
+            // Cross accounts already paid the excess from their free balance
+            let shortfall = match margin_mode {
+                MarginMode::Isolated => (-equity).max(Decimal::ZERO),
+                MarginMode::Cross => users
+                    .iter()
+                    .find(|u| u.id == user_id)
+                    .map_or(Decimal::ZERO, |user| {
+                        (-user.free_balance(quote_asset)).max(Decimal::ZERO)
                     }),
+            };
+            let fund_balance = users
+                .iter()
+                .find(|u| u.id == INSURANCE_FUND_ACCOUNT)
+                .and_then(|fund| fund.balances.iter().find(|b| b.ticker == quote_asset))
+                .map_or(Decimal::ZERO, |b| b.balance);
+            let draw = shortfall.min(fund_balance.max(Decimal::ZERO));
+
+            if let Some(user) = users.iter_mut().find(|u| u.id == user_id) {
+                user.credit(quote_asset, draw - fee);
+            }
+            if let Some(fund) = users.iter_mut().find(|u| u.id == INSURANCE_FUND_ACCOUNT) {
+                fund.credit(quote_asset, fee - draw);
+            }
+            (draw, shortfall)
+        };
+        if insurance_fund_draw < shortfall {
+            warn!(
+                ?market,
+                ?user_id,
+                uncovered = ?(shortfall - insurance_fund_draw),
+                "Insurance fund could not cover the liquidation shortfall"
+            );
+        }
+
+        let bankruptcy_price = backed.bankruptcy_price();
+        let quantity = filled_qty + deleveraged_quantity;
+        let event = LiquidationEvent {
+            market: market.to_string(),
+            user_id: user_id.to_string(),
+            position_type,
+            quantity,
+            price: (filled_value + bankruptcy_price * deleveraged_quantity) / quantity,
+            mark_price,
+            realized_pnl: realized_pnl - position.collateral * deleveraged_quantity / position.size,
+            fee,
+            insurance_fund_draw,
+            deleveraged_quantity,
+            timestamp: Utc::now().timestamp(),
+        };
+        info!(?event, "Liquidated position");
+
+        let redis_manager = RedisManager::instance();
+        let _ = redis_manager.publish_message(
+            &format!("liquidation@{}", market),
+            &serde_json::to_value(&event).unwrap(),
+        );
+        Ok(event)
+    }
+
+    /// Closes up to `quantity` against the opposite ADL queue, returning the closed quantity.
+    async fn auto_deleverage(
+        &self,
+        market: &str,
+        position: &MarginPosition,
+        quantity: Decimal,
+        mark_price: Decimal,
+    ) -> Decimal {
+        let quote_asset = market.split('_').nth(1).unwrap_or("USDC");
+        let price = position.bankruptcy_price();
+        let opposite = match position.position_type {
+            PositionType::Long => PositionType::Short,
+            PositionType::Short => PositionType::Long,
+        };
+
+        let mut events = Vec::new();
+        let mut remaining = quantity;
+        {
+            let mut users = self.users.write().await;
+            let queue: Vec<(String, Decimal)> = adl_queue(&users, market, &opposite, mark_price)
+                .into_iter()
+                .filter(|p| p.user_id != position.user_id)
+                .map(|p| (p.user_id.clone(), p.size))
+                .collect();
+
+            for (user_id, size) in queue {
+                if remaining.is_zero() {
+                    break;
+                }
+                let Some(user) = users.iter_mut().find(|u| u.id == user_id) else {
+                    continue;
                 };
-                let _ = redis_manager.send_to_api(&client_id, &message);
+                let closed = remaining.min(size);
+                let realized_pnl =
+                    user.close_position(market, &opposite, closed, price, quote_asset);
+                remaining -= closed;
+
+                events.push(AdlEvent {
+                    market: market.to_string(),
+                    user_id,
+                    position_type: opposite.clone(),
+                    quantity: closed,
+                    price,
+                    realized_pnl,
+                    remaining_size: size - closed,
+                    timestamp: Utc::now().timestamp(),
+                });
+            }
+
+            if let Some(user) = users.iter_mut().find(|u| u.id == position.user_id) {
+                user.close_position(
+                    market,
+                    &position.position_type,
+                    quantity - remaining,
+                    price,
+                    quote_asset,
+                );
             }
         }
+
+        let redis_manager = RedisManager::instance();
+        for event in events {
+            warn!(?event, "Auto-deleveraged position");
+            let _ = redis_manager.publish_message(
+                &format!("adl@{}", event.user_id),
+                &serde_json::to_value(&event).unwrap(),
+            );
+        }
+        quantity - remaining
+    }
+
+    /// Moves funding between the collateral of a market's longs and shorts.
+    pub async fn settle_funding(&self, funding_rate: &FundingRate) {
+        let quote_asset = funding_rate.market.split('_').nth(1).unwrap_or("USDC");
+        let mut users = self.users.write().await;
+        let mut positions = 0;
+
+        for user in users.iter_mut() {
+            let mut received = Decimal::ZERO;
+            for position in user
+                .margin_positions
+                .iter_mut()
+                .filter(|p| p.asset == funding_rate.market)
+            {
+                let payment = position.size * funding_rate.mark_price * funding_rate.rate;
+                let delta = match position.position_type {
+                    PositionType::Long => -payment,
+                    PositionType::Short => payment,
+                };
+                position.collateral += delta;
+                received += delta;
+                positions += 1;
+            }
+
+            if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == quote_asset) {
+                balance.balance += received;
+                balance.locked_balance += received;
+            }
+        }
+
+        info!(
+            market = ?funding_rate.market,
+            rate = ?funding_rate.rate,
+            positions,
+            "Settled funding"
+        );
+        let redis_manager = RedisManager::instance();
+        let _ = redis_manager.publish_message(
+            &format!("funding@{}", funding_rate.market),
+            &serde_json::to_value(funding_rate).unwrap(),
+        );
+    }
+
+    fn publish_market_status(market: &str, status: MarketStatus, reason: Option<String>) {
+        let status_info = MarketStatusPayload {
+            market: market.to_string(),
+            status,
+            reason,
+        };
+        let _ = RedisManager::instance().publish_message(
+            &format!("status@{}", market),
+            &serde_json::to_value(&status_info).unwrap(),
+        );
+    }
+
+    async fn market_specs(&self) -> Vec<MarketSpec> {
+        let orderbooks = self.orderbooks.lock().await;
+        let mut markets = Vec::new();
+
+        for orderbook in orderbooks.values() {
+            markets.push(orderbook.lock().await.spec.clone());
+        }
+        markets.sort_by(|a, b| a.market.cmp(&b.market));
+        markets
     }
 
     pub async fn create_order(
         &mut self,
         payload: &CreateOrderPayload,
-    ) -> Result<(Decimal, Decimal, String), Box<dyn std::error::Error>> {
+    ) -> Result<OrderPlacedPayload, Box<dyn std::error::Error>> {
         let order_id = Uuid::new_v4().to_string();
 
+        let (spec, status, band_limits) = {
+            let orderbooks = self.orderbooks.lock().await;
+            let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+            let orderbook = orderbook.lock().await;
+            (
+                orderbook.spec.clone(),
+                orderbook.status,
+                orderbook.price_band_limits(),
+            )
+        };
+
+        if !status.allows_new_orders() {
+            warn!(market = ?payload.market, ?status, "Market is not accepting orders");
+            return Err(format!("Market is {:?} and not accepting orders", status).into());
+        }
+
+        let limit_price = match payload.order_type {
+            OrderType::Market | OrderType::StopMarket | OrderType::TakeProfit => None,
+            _ => Some(payload.price),
+        };
+        let validation = spec
+            .validate_order(limit_price, payload.quantity)
+            .and_then(|_| match payload.trigger_price {
+                Some(trigger_price) => spec.validate_price(trigger_price),
+                None => Ok(()),
+            });
+        if let Err(reason) = validation {
+            warn!(user_id = ?payload.user_id, market = ?payload.market, reason, "Order rejected by market rules");
+            return Err(reason.into());
+        }
+
+        if let Some(price) = limit_price {
+            if let Err(reason) = spec.validate_band(price, band_limits) {
+                warn!(market = ?payload.market, ?price, ?band_limits, "Order priced outside the price band");
+                return Err(reason.into());
+            }
+        }
+
+        if payload
+            .display_quantity
+            .is_some_and(|display_quantity| display_quantity <= Decimal::ZERO)
+        {
+            warn!(user_id = ?payload.user_id, "Iceberg display quantity must be positive");
+            return Err("Iceberg display quantity must be positive".into());
+        }
+
         match payload.order_type {
-            OrderType::MarginLong | OrderType::MarginShort => {
-                if !self.validate_margin_requirements(&payload).await {
-                    error!("Insufficient balance for margin trade");
-                    return Err("Insufficient balance for margin trade".into());
-                }
+            OrderType::StopMarket | OrderType::StopLimit | OrderType::TakeProfit => {
+                self.place_trigger_order(order_id, payload).await
             }
-            OrderType::Spot => {
-                if !self.validate_spot_balance(&payload).await {
-                    error!("Insufficient balance for spot trade");
-                    return Err("Insufficient balance for spot trade".into());
-                }
+            _ => self.execute_order(order_id, payload).await,
+        }
+    }
+
+    async fn place_trigger_order(
+        &mut self,
+        order_id: String,
+        payload: &CreateOrderPayload,
+    ) -> Result<OrderPlacedPayload, Box<dyn std::error::Error>> {
+        let trigger_price = payload
+            .trigger_price
+            .ok_or("Trigger price is required for trigger orders")?;
+
+        if payload.is_margin {
+            return Err("Trigger orders are only supported for spot trading".into());
+        }
+
+        if payload.order_type == OrderType::StopLimit && payload.price <= Decimal::ZERO {
+            return Err("Stop-limit orders require a limit price".into());
+        }
+
+        let orderbooks = self.orderbooks.lock().await;
+        let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+
+        let mut orderbook_guard = orderbook.lock().await;
+        let fee_asset = orderbook_guard
+            .fee_asset(&payload.side, payload.is_margin)
+            .to_string();
+        orderbook_guard.trigger_orders.push(Order {
+            id: order_id.clone(),
+            user_id: payload.user_id.clone(),
+            price: payload.price,
+            quantity: payload.quantity,
+            side: payload.side.clone(),
+            is_margin: false,
+            leverage: None,
+            timestamp: Utc::now().timestamp(),
+            order_type: payload.order_type,
+            trigger_price: Some(trigger_price),
+            iceberg: None,
+            reserved_margin: Decimal::ZERO,
+        });
+
+        info!(
+            order_id = ?order_id,
+            order_type = ?payload.order_type,
+            trigger_price = ?trigger_price,
+            "Trigger order placed"
+        );
+        Ok(OrderPlacedPayload {
+            order_id,
+            remaining_qty: payload.quantity,
+            filled_qty: Decimal::ZERO,
+            fee: Decimal::ZERO,
+            fee_asset,
+            fills: Vec::new(),
+            prevented_matches: Vec::new(),
+        })
+    }
+
+    pub async fn execute_trigger_order(&mut self, market: &str, order: Order) {
+        let (order_type, price, time_in_force) = match order.order_type {
+            OrderType::StopLimit => (OrderType::Spot, order.price, TimeInForce::Gtc),
+            _ => (OrderType::Market, Decimal::ZERO, TimeInForce::Ioc),
+        };
+
+        let payload = CreateOrderPayload {
+            user_id: order.user_id.clone(),
+            market: market.to_string(),
+            price,
+            quantity: order.quantity,
+            side: order.side.clone(),
+            order_type,
+            is_margin: false,
+            leverage: None,
+            time_in_force,
+            trigger_price: None,
+            display_quantity: None,
+            self_trade_prevention: None,
+            reduce_only: false,
+        };
+
+        info!(order_id = ?order.id, trigger_price = ?order.trigger_price, "Trigger order activated");
+
+        if let Err(e) = self.execute_order(order.id.clone(), &payload).await {
+            error!(order_id = ?order.id, "Failed to execute trigger order: {}", e);
+            let rejected = TriggerRejectedEvent {
+                order_id: order.id,
+                user_id: order.user_id,
+                market: market.to_string(),
+                order_type: order.order_type,
+                side: order.side,
+                quantity: order.quantity,
+                trigger_price: order.trigger_price,
+                reason: e.to_string(),
+                timestamp: Utc::now().timestamp(),
+            };
+            if let Some(user) = self
+                .users
+                .write()
+                .await
+                .iter_mut()
+                .find(|u| u.id == rejected.user_id)
+            {
+                user.record_rejected_trigger(rejected.clone());
             }
+            let _ = RedisManager::instance().publish_message(
+                &format!("trigger_rejected@{}", rejected.user_id),
+                &serde_json::to_value(&rejected).unwrap(),
+            );
         }
+    }
+
+    async fn execute_order(
+        &mut self,
+        order_id: String,
+        payload: &CreateOrderPayload,
+    ) -> Result<OrderPlacedPayload, Box<dyn std::error::Error>> {
+        self.check_time_in_force(payload).await?;
+
+        let reserved = match payload.order_type {
+            _ if payload.reduce_only => {
+                self.check_reduce_only(payload).await?;
+                None
+            }
+            _ if payload.is_margin => match self.validate_margin_requirements(payload).await {
+                Ok(reserved) => Some(reserved),
+                Err(reason) => {
+                    error!("{}", reason);
+                    return Err(reason.into());
+                }
+            },
+            _ => match self.validate_spot_balance(&payload).await {
+                Ok(reserved) => Some(reserved),
+                Err(reason) => {
+                    error!("{}", reason);
+                    return Err(reason.into());
+                }
+            },
+        };
 
         let orderbooks = self.orderbooks.lock().await;
         let orderbook = orderbooks
@@ -359,25 +1406,129 @@ impl Engine {
             .ok_or("Market not found")
             .unwrap();
 
-        let remaining_qty = orderbook
-            .lock()
-            .await
-            .fill_orders(
-                payload,
-                &mut self.users,
-                &payload.market.split('_').nth(0).unwrap().to_string(),
-                &payload.market.split('_').nth(1).unwrap().to_string(),
-            )
-            .await;
+        let mut market_assets = payload.market.split('_');
+        let base_asset = market_assets.next().unwrap();
+        let quote_asset = market_assets.next().unwrap();
+
+        let (fill, fee_asset, ticker) = {
+            let mut orderbook_guard = orderbook.lock().await;
+            let fill = orderbook_guard
+                .fill_orders(&order_id, payload, &mut self.users, base_asset, quote_asset)
+                .await;
+
+            if let Some(price_move) = orderbook_guard.trip_circuit_breaker(Utc::now().timestamp()) {
+                warn!(
+                    market = ?payload.market,
+                    ?price_move,
+                    halted_until = ?orderbook_guard.circuit_breaker.halted_until,
+                    "Circuit breaker tripped, halting market"
+                );
+                Self::publish_market_status(
+                    &payload.market,
+                    orderbook_guard.status,
+                    Some(format!(
+                        "Price moved {}% within the window",
+                        (price_move * dec!(100)).round_dp(2)
+                    )),
+                );
+            }
+            let fee_asset = orderbook_guard
+                .fee_asset(&payload.side, payload.is_margin)
+                .to_string();
+            let ticker = if fill.fills.is_empty() {
+                None
+            } else {
+                Some(orderbook_guard.ticker().await)
+            };
+            (fill, fee_asset, ticker)
+        };
+        let remaining_qty = fill.remaining_qty;
+
+        let redis_manager = RedisManager::instance();
+        for trade in &fill.fills {
+            let _ = redis_manager.publish_message(
+                &format!("trade@{}", payload.market),
+                &serde_json::to_value(trade).unwrap(),
+            );
+        }
+        if let Some(ticker) = ticker {
+            let _ = redis_manager.publish_message(
+                &format!("ticker@{}", payload.market),
+                &serde_json::to_value(ticker).unwrap(),
+            );
+        }
+
+        let rests_on_book = remaining_qty > Decimal::ZERO
+            && payload.order_type != OrderType::Market
+            && !payload.reduce_only
+            && matches!(
+                payload.time_in_force,
+                TimeInForce::Gtc | TimeInForce::PostOnly
+            );
 
-        if remaining_qty == Decimal::from(0) {
-            return Ok((Decimal::ZERO, payload.quantity, order_id.clone()));
+        if let Some(reserved) = reserved {
+            let (ticker, unused) = if payload.is_margin {
+                // Only a cancelled remainder's margin is released
+                let cancelled = if rests_on_book {
+                    Decimal::ZERO
+                } else {
+                    remaining_qty
+                };
+                (quote_asset, reserved * cancelled / payload.quantity)
+            } else {
+                let (ticker, spent, resting) = match payload.side {
+                    OrderSide::Buy => (
+                        quote_asset,
+                        fill.filled_value,
+                        payload.price * remaining_qty,
+                    ),
+                    OrderSide::Sell => (base_asset, fill.filled_qty, remaining_qty),
+                };
+                let unused = reserved
+                    - spent
+                    - if rests_on_book {
+                        resting
+                    } else {
+                        Decimal::ZERO
+                    };
+                (ticker, unused)
+            };
+            if unused > Decimal::ZERO {
+                self.unlock_balance(&payload.user_id, ticker, unused).await;
+            }
+        }
+
+        let placed = OrderPlacedPayload {
+            order_id: order_id.clone(),
+            remaining_qty,
+            filled_qty: fill.filled_qty,
+            fee: fill.fee,
+            fee_asset,
+            fills: fill.fills,
+            prevented_matches: fill.prevented_matches,
+        };
+
+        if !rests_on_book {
+            if remaining_qty > Decimal::ZERO {
+                info!(
+                    remaining_qty = ?remaining_qty,
+                    filled_qty = ?placed.filled_qty,
+                    order_id = ?order_id,
+                    time_in_force = ?payload.time_in_force,
+                    "Order remainder cancelled"
+                );
+            }
+            return Ok(placed);
         }
 
+        let reserved_margin = match reserved {
+            Some(reserved) if payload.is_margin => reserved * remaining_qty / payload.quantity,
+            _ => Decimal::ZERO,
+        };
         match payload.side {
             OrderSide::Buy => {
                 let mut orderbook_guard = orderbook.lock().await;
-                orderbook_guard.bids.push(Order {
+                orderbook_guard.insert_order(Order {
                     id: order_id.clone(),
                     user_id: payload.user_id.clone(),
                     price: payload.price,
@@ -386,29 +1537,26 @@ impl Engine {
                     is_margin: payload.is_margin,
                     leverage: payload.leverage,
                     timestamp: Utc::now().timestamp(),
-                });
-
-                orderbook_guard.bids.sort_by(|a, b| {
-                    b.price
-                        .cmp(&a.price)
-                        .then_with(|| a.timestamp.cmp(&b.timestamp))
+                    order_type: payload.order_type,
+                    trigger_price: None,
+                    iceberg: payload
+                        .display_quantity
+                        .map(|display_quantity| Iceberg::new(display_quantity, remaining_qty)),
+                    reserved_margin,
                 });
 
                 let redis_manager = RedisManager::instance();
                 let depth = orderbook_guard.get_depth();
-                let price_info = orderbook_guard.get_price_info().await;
+                let ticker = orderbook_guard.ticker().await;
 
                 let _ = redis_manager.publish_message(
                     &format!("depth@{}", payload.market),
                     &serde_json::to_value(depth).unwrap(),
                 );
-
-                if let Some(price) = price_info {
-                    let _ = redis_manager.publish_message(
-                        &format!("ticker@{}", payload.market),
-                        &serde_json::to_value(price).unwrap(),
-                    );
-                }
+                let _ = redis_manager.publish_message(
+                    &format!("ticker@{}", payload.market),
+                    &serde_json::to_value(ticker).unwrap(),
+                );
 
                 let trade_info = AddTradePayload {
                     data: TradeData {
@@ -422,7 +1570,7 @@ impl Engine {
             }
             OrderSide::Sell => {
                 let mut orderbook_guard = orderbook.lock().await;
-                orderbook_guard.asks.push(Order {
+                orderbook_guard.insert_order(Order {
                     id: order_id.clone(),
                     user_id: payload.user_id.clone(),
                     price: payload.price,
@@ -431,42 +1579,37 @@ impl Engine {
                     is_margin: payload.is_margin,
                     leverage: payload.leverage,
                     timestamp: Utc::now().timestamp(),
-                });
-
-                orderbook_guard.asks.sort_by(|a, b| {
-                    a.price
-                        .cmp(&b.price)
-                        .then_with(|| a.timestamp.cmp(&b.timestamp))
+                    order_type: payload.order_type,
+                    trigger_price: None,
+                    iceberg: payload
+                        .display_quantity
+                        .map(|display_quantity| Iceberg::new(display_quantity, remaining_qty)),
+                    reserved_margin,
                 });
 
                 // Publish updates
                 let redis_manager = RedisManager::instance();
                 let depth = orderbook_guard.get_depth();
-                let price_info = orderbook_guard.get_price_info().await;
+                let ticker = orderbook_guard.ticker().await;
 
                 let _ = redis_manager.publish_message(
                     &format!("depth@{}", payload.market),
                     &serde_json::to_value(depth).unwrap(),
                 );
-
-                if let Some(price) = price_info {
-                    let _ = redis_manager.publish_message(
-                        &format!("ticker@{}", payload.market),
-                        &serde_json::to_value(price).unwrap(),
-                    );
-                }
+                let _ = redis_manager.publish_message(
+                    &format!("ticker@{}", payload.market),
+                    &serde_json::to_value(ticker).unwrap(),
+                );
             }
         }
 
-        let filled_qty = payload.quantity.checked_sub(remaining_qty).unwrap();
-
         info!(
             remaining_qty = ?remaining_qty,
-            filled_qty = ?filled_qty,
+            filled_qty = ?placed.filled_qty,
             order_id = ?order_id,
             "Order created successfully"
         );
-        Ok((remaining_qty, filled_qty, order_id))
+        Ok(placed)
     }
 
     pub async fn cancel_order(
@@ -477,50 +1620,275 @@ impl Engine {
         let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
 
         let mut orderbook_guard = orderbook.lock().await;
+        if !orderbook_guard.status.allows_cancels() {
+            return Err(format!(
+                "Market is {:?} and not accepting cancels",
+                orderbook_guard.status
+            )
+            .into());
+        }
 
-        if let Some(bid_index) = orderbook_guard
-            .bids
+        if let Some(order) = orderbook_guard.remove_order(&payload.order_id) {
+            let (ticker, locked_amount) = Self::locked_for_order(&payload.market, &order);
+            self.unlock_balance(&order.user_id, ticker, locked_amount)
+                .await;
+
+            return Ok(());
+        }
+
+        if let Some(trigger_index) = orderbook_guard
+            .trigger_orders
             .iter()
             .position(|order| order.id == payload.order_id)
         {
-            let order = &orderbook_guard.bids[bid_index];
+            let _ = orderbook_guard.trigger_orders.remove(trigger_index);
+            return Ok(());
+        }
 
-            let mut users = self.users.write().await;
-            if let Some(user) = users.iter_mut().find(|u| u.id == order.user_id) {
-                let quote_asset = payload.market.split('_').nth(1).unwrap();
-                if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == quote_asset) {
-                    let locked_amount = order.price * order.quantity;
+        Err("Order not found".into())
+    }
+
+    pub async fn cancel_all_orders(
+        &mut self,
+        payload: &CancelAllOrdersPayload,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let orderbooks = self.orderbooks.lock().await;
+        let markets: Vec<(&String, &Arc<Mutex<Orderbook>>)> = match &payload.market {
+            Some(market) => vec![orderbooks.get_key_value(market).ok_or("Market not found")?],
+            None => orderbooks.iter().collect(),
+        };
+
+        let redis_manager = RedisManager::instance();
+        let mut cancelled = Vec::new();
+
+        for (market, orderbook) in markets {
+            let mut orderbook_guard = orderbook.lock().await;
+            if !orderbook_guard.status.allows_cancels() {
+                if payload.market.is_some() {
+                    return Err(format!(
+                        "Market is {:?} and not accepting cancels",
+                        orderbook_guard.status
+                    )
+                    .into());
+                }
+                continue;
+            }
+
+            let removed =
+                orderbook_guard.remove_user_orders(&payload.user_id, payload.side.as_ref());
+            if removed.is_empty() {
+                continue;
+            }
+
+            let _ = redis_manager.publish_message(
+                &format!("depth@{}", market),
+                &serde_json::to_value(orderbook_guard.get_depth()).unwrap(),
+            );
+            cancelled.extend(removed.into_iter().map(|order| (market.clone(), order)));
+        }
+
+        let mut users = self.users.write().await;
+        if let Some(user) = users.iter_mut().find(|u| u.id == payload.user_id) {
+            for (market, order) in cancelled.iter().filter(|(_, o)| o.trigger_price.is_none()) {
+                let (ticker, locked_amount) = Self::locked_for_order(market, order);
+                if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == ticker) {
                     balance.locked_balance -= locked_amount;
                 }
             }
+        }
 
-            let _ = orderbook_guard.bids.remove(bid_index);
-            return Ok(());
+        Ok(cancelled.into_iter().map(|(_, order)| order.id).collect())
+    }
+
+    fn locked_for_order<'a>(market: &'a str, order: &Order) -> (&'a str, Decimal) {
+        let mut market_assets = market.split('_');
+        let base_asset = market_assets.next().unwrap();
+        let quote_asset = market_assets.next().unwrap();
+
+        match order.side {
+            _ if order.is_margin => (quote_asset, order.reserved_margin),
+            OrderSide::Buy => (quote_asset, order.price * order.quantity),
+            OrderSide::Sell => (base_asset, order.quantity),
         }
+    }
 
-        if let Some(ask_index) = orderbook_guard
-            .asks
-            .iter()
-            .position(|order| order.id == payload.order_id)
-        {
-            let order = &orderbook_guard.asks[ask_index];
+    pub async fn amend_order(
+        &mut self,
+        payload: &AmendOrderPayload,
+    ) -> Result<Order, Box<dyn std::error::Error>> {
+        let orderbooks = self.orderbooks.lock().await;
+        let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+        let mut orderbook_guard = orderbook.lock().await;
+        if !orderbook_guard.status.allows_new_orders() {
+            return Err(format!(
+                "Market is {:?} and not accepting amends",
+                orderbook_guard.status
+            )
+            .into());
+        }
+
+        let order = orderbook_guard
+            .get_order(&payload.order_id)
+            .ok_or("Order not found")?
+            .clone();
+
+        if order.user_id != payload.user_id {
+            return Err("Order does not belong to user".into());
+        }
+        if order.is_margin {
+            return Err("Margin orders cannot be amended".into());
+        }
+
+        let price = payload.price.unwrap_or(order.price);
+        let quantity = payload.quantity.unwrap_or(order.quantity);
+        if price <= Decimal::ZERO || quantity <= Decimal::ZERO {
+            return Err("Amended price and quantity must be positive".into());
+        }
+        orderbook_guard.spec.validate_order(Some(price), quantity)?;
+        orderbook_guard
+            .spec
+            .validate_band(price, orderbook_guard.price_band_limits())?;
 
+        if price != order.price && orderbook_guard.would_cross(&order.side, price) {
+            return Err("Amended order would cross the book".into());
+        }
+
+        let mut market_assets = payload.market.split('_');
+        let base_asset = market_assets.next().unwrap();
+        let quote_asset = market_assets.next().unwrap();
+        let (ticker, locked_delta) = match order.side {
+            OrderSide::Buy => (quote_asset, price * quantity - order.price * order.quantity),
+            OrderSide::Sell => (base_asset, quantity - order.quantity),
+        };
+
+        {
             let mut users = self.users.write().await;
-            if let Some(user) = users.iter_mut().find(|u| u.id == order.user_id) {
-                let base_asset = payload.market.split('_').nth(0).unwrap();
-                if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == base_asset) {
-                    balance.locked_balance -= order.quantity;
-                }
+            let balance = users
+                .iter_mut()
+                .find(|u| u.id == order.user_id)
+                .and_then(|u| u.balances.iter_mut().find(|b| b.ticker == ticker))
+                .ok_or("Balance not found")?;
+
+            if locked_delta > balance.balance - balance.locked_balance {
+                return Err("Insufficient balance to amend order".into());
             }
+            balance.locked_balance += locked_delta;
+        }
 
-            let _ = &orderbook_guard.asks.remove(ask_index);
-            return Ok(());
+        let amended = orderbook_guard
+            .amend_order(&order.id, price, quantity)
+            .ok_or("Order not found")?;
+
+        let redis_manager = RedisManager::instance();
+        let _ = redis_manager.publish_message(
+            &format!("depth@{}", payload.market),
+            &serde_json::to_value(orderbook_guard.get_depth()).unwrap(),
+        );
+
+        Ok(amended)
+    }
+
+    async fn check_time_in_force(
+        &self,
+        payload: &CreateOrderPayload,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let orderbooks = self.orderbooks.lock().await;
+        let orderbook = orderbooks
+            .get(&payload.market)
+            .ok_or("Market not found")?
+            .lock()
+            .await;
+
+        let limit_price = match payload.order_type {
+            OrderType::Market => None,
+            _ => Some(payload.price),
+        };
+
+        if payload.time_in_force == TimeInForce::PostOnly
+            || orderbook.status == MarketStatus::PostOnly
+        {
+            let crosses = match limit_price {
+                Some(price) => orderbook.would_cross(&payload.side, price),
+                None => true,
+            };
+            if crosses {
+                warn!(user_id = ?payload.user_id, "Post-only order would cross the book");
+                return Err("Post-only order would cross the book".into());
+            }
         }
 
-        Err("Order not found".into())
+        if payload.time_in_force == TimeInForce::Fok
+            && orderbook.fillable_quantity(payload) < payload.quantity
+        {
+            warn!(user_id = ?payload.user_id, "Fill-or-kill order cannot be fully filled");
+            return Err("Fill-or-kill order cannot be fully filled".into());
+        }
+
+        Ok(())
     }
 
-    async fn validate_margin_requirements(&self, payload: &CreateOrderPayload) -> bool {
+    /// Reduce-only orders are IOC or FOK margin orders no larger than the position.
+    async fn check_reduce_only(
+        &self,
+        payload: &CreateOrderPayload,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !payload.is_margin {
+            return Err("Reduce-only orders are only supported for margin trading".into());
+        }
+        if payload.order_type != OrderType::Market
+            && !matches!(payload.time_in_force, TimeInForce::Ioc | TimeInForce::Fok)
+        {
+            return Err("Reduce-only limit orders must be IOC or FOK".into());
+        }
+
+        let reduced = match payload.side {
+            OrderSide::Buy => PositionType::Short,
+            OrderSide::Sell => PositionType::Long,
+        };
+        let users = self.users.read().await;
+        let position_size = users
+            .iter()
+            .find(|u| u.id == payload.user_id)
+            .and_then(|user| {
+                user.margin_positions
+                    .iter()
+                    .find(|p| p.asset == payload.market && p.position_type == reduced)
+            })
+            .map_or(Decimal::ZERO, |p| p.size);
+
+        if payload.quantity > position_size {
+            return Err(format!(
+                "Reduce-only order of {} exceeds the {:?} position of {}",
+                payload.quantity, reduced, position_size
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Locks the margin an order needs and returns the amount locked.
+    async fn validate_margin_requirements(
+        &self,
+        payload: &CreateOrderPayload,
+    ) -> Result<Decimal, &'static str> {
+        let price = match payload.order_type {
+            OrderType::Market => {
+                let orderbooks = self.orderbooks.lock().await;
+                let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+                let quote = orderbook.lock().await.market_order_quote(
+                    payload.quantity,
+                    payload.side.clone(),
+                    &payload.user_id,
+                );
+                if quote.total_cost == Decimal::ZERO {
+                    warn!(market = ?payload.market, "No liquidity for market order");
+                    return Err("No liquidity for market order");
+                }
+                quote.avg_price
+            }
+            _ => payload.price,
+        };
+
         let mut users = self.users.write().await;
         let user = users
             .iter_mut()
@@ -529,7 +1897,7 @@ impl Engine {
 
         if !user.margin_enabled {
             warn!(user_id = ?user.id, "Margin trading not enabled for user");
-            return false;
+            return Err("Margin trading not enabled for user");
         }
 
         let leverage = payload.leverage.unwrap_or(dec!(1));
@@ -540,71 +1908,115 @@ impl Engine {
                 max = ?user.max_leverage,
                 "Requested leverage exceeds maximum"
             );
-            return false;
+            return Err("Requested leverage exceeds maximum");
         }
 
-        let usdc_balance = user
-            .balances
-            .iter()
-            .find(|b| b.ticker == "USDC")
-            .map(|b| b.balance - b.locked_balance)
-            .unwrap_or(dec!(0));
+        let quote_asset = payload.market.split('_').nth(1).unwrap_or("USDC");
+        let quote_balance = user.free_balance(quote_asset);
+        // Unrealized cross losses are backed by the free balance
+        let quote_balance = match user.margin_mode(&payload.market) {
+            MarginMode::Isolated => quote_balance,
+            MarginMode::Cross => {
+                let cross_pnl: Decimal = user
+                    .margin_positions
+                    .iter()
+                    .filter(|p| user.margin_mode(&p.asset) == MarginMode::Cross)
+                    .filter(|p| p.asset.split('_').nth(1) == Some(quote_asset))
+                    .map(|p| p.unrealized_pnl)
+                    .sum();
+                quote_balance + cross_pnl.min(Decimal::ZERO)
+            }
+        };
 
-        let position_value = payload.price * payload.quantity;
+        let position_value = price * payload.quantity;
         let required_margin = position_value / leverage;
 
         let total_margin_used = user.margin_used + required_margin;
-        let max_margin_allowed = usdc_balance * user.max_leverage;
+        let max_margin_allowed = quote_balance * user.max_leverage;
 
         if total_margin_used > max_margin_allowed {
-            return false;
+            return Err("Insufficient balance for margin trade");
         }
 
-        match payload.order_type {
-            OrderType::MarginLong => {
-                if let Some(existing_short) = user.margin_positions.iter().find(|p| {
-                    p.asset == payload.market.split('_').next().unwrap()
-                        && p.position_type == PositionType::Short
-                }) {
+        let position_type = match (payload.order_type, &payload.side) {
+            (OrderType::MarginLong, _) | (OrderType::Market, OrderSide::Buy) => PositionType::Long,
+            (OrderType::MarginShort, _) | (OrderType::Market, OrderSide::Sell) => {
+                PositionType::Short
+            }
+            _ => return Ok(Decimal::ZERO),
+        };
+        match position_type {
+            PositionType::Long => {
+                if let Some(existing_short) = user
+                    .margin_positions
+                    .iter()
+                    .find(|p| p.asset == payload.market && p.position_type == PositionType::Short)
+                {
                     if existing_short.size >= payload.quantity {
-                        return true;
+                        return Ok(Decimal::ZERO);
                     }
                 }
 
-                if usdc_balance >= required_margin {
-                    if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == "USDC") {
+                if quote_balance >= required_margin {
+                    if let Some(balance) =
+                        user.balances.iter_mut().find(|b| b.ticker == quote_asset)
+                    {
                         balance.locked_balance += required_margin;
-                        return true;
+                        return Ok(required_margin);
                     }
                 }
             }
-            OrderType::MarginShort => {
-                if let Some(existing_long) = user.margin_positions.iter().find(|p| {
-                    p.asset == payload.market.split('_').next().unwrap()
-                        && p.position_type == PositionType::Long
-                }) {
+            PositionType::Short => {
+                if let Some(existing_long) = user
+                    .margin_positions
+                    .iter()
+                    .find(|p| p.asset == payload.market && p.position_type == PositionType::Long)
+                {
                     if existing_long.size >= payload.quantity {
-                        return true;
+                        return Ok(Decimal::ZERO);
                     }
                 }
 
                 let safety_multiplier = dec!(1.1);
                 let adjusted_required_margin = required_margin * safety_multiplier;
 
-                if usdc_balance >= adjusted_required_margin {
-                    if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == "USDC") {
+                if quote_balance >= adjusted_required_margin {
+                    if let Some(balance) =
+                        user.balances.iter_mut().find(|b| b.ticker == quote_asset)
+                    {
                         balance.locked_balance += adjusted_required_margin;
-                        return true;
+                        return Ok(adjusted_required_margin);
                     }
                 }
             }
-            OrderType::Spot => return true,
         }
 
-        false
+        Err("Insufficient balance for margin trade")
     }
 
-    async fn validate_spot_balance(&self, payload: &CreateOrderPayload) -> bool {
+    /// Locks the balance an order can spend and returns the amount locked.
+    async fn validate_spot_balance(
+        &self,
+        payload: &CreateOrderPayload,
+    ) -> Result<Decimal, &'static str> {
+        let market_quote = match payload.order_type {
+            OrderType::Market => {
+                let orderbooks = self.orderbooks.lock().await;
+                let orderbook = orderbooks.get(&payload.market).ok_or("Market not found")?;
+                let quote = orderbook.lock().await.market_order_quote(
+                    payload.quantity,
+                    payload.side.clone(),
+                    &payload.user_id,
+                );
+                if quote.total_cost == Decimal::ZERO {
+                    warn!(market = ?payload.market, "No liquidity for market order");
+                    return Err("No liquidity for market order");
+                }
+                Some(quote)
+            }
+            _ => None,
+        };
+
         let mut users = self.users.write().await;
         let user = users
             .iter_mut()
@@ -613,46 +2025,52 @@ impl Engine {
 
         match payload.side {
             OrderSide::Buy => {
-                let usdc_balance = user
-                    .balances
-                    .iter()
-                    .find(|b| b.ticker == "USDC".to_string())
-                    .map(|b| b.balance - b.locked_balance)
-                    .unwrap_or(dec!(0));
-                let required_amount = payload.price * payload.quantity;
-                if usdc_balance >= required_amount {
-                    if let Some(balance) = user
-                        .balances
-                        .iter_mut()
-                        .find(|b| b.ticker == "USDC".to_string())
+                let quote_asset = payload.market.split('_').nth(1).unwrap_or("USDC");
+                let quote_balance = user.free_balance(quote_asset);
+                let required_amount = match market_quote {
+                    Some(quote) => quote.total_cost,
+                    None => payload.price * payload.quantity,
+                };
+                if quote_balance >= required_amount {
+                    if let Some(balance) =
+                        user.balances.iter_mut().find(|b| b.ticker == quote_asset)
                     {
                         balance.locked_balance += required_amount;
                     }
-                    true
+                    Ok(required_amount)
                 } else {
-                    false
+                    Err("Insufficient balance for spot trade")
                 }
             }
             OrderSide::Sell => {
-                let sol_balance = user
+                let base_asset = payload.market.split('_').next().unwrap();
+                let base_balance = user
                     .balances
                     .iter()
-                    .find(|b| b.ticker == "SOL".to_string())
+                    .find(|b| b.ticker == base_asset)
                     .map(|b| b.balance)
                     .unwrap_or(dec!(0));
-                if sol_balance >= payload.quantity {
-                    if let Some(balance) = user
-                        .balances
-                        .iter_mut()
-                        .find(|b| b.ticker == "SOL".to_string())
+                if base_balance >= payload.quantity {
+                    if let Some(balance) = user.balances.iter_mut().find(|b| b.ticker == base_asset)
                     {
                         balance.locked_balance += payload.quantity;
                     }
-                    true
+                    Ok(payload.quantity)
                 } else {
-                    false
+                    Err("Insufficient balance for spot trade")
                 }
             }
         }
     }
+
+    async fn unlock_balance(&self, user_id: &str, ticker: &str, amount: Decimal) {
+        let mut users = self.users.write().await;
+        if let Some(balance) = users
+            .iter_mut()
+            .find(|u| u.id == user_id)
+            .and_then(|u| u.balances.iter_mut().find(|b| b.ticker == ticker))
+        {
+            balance.locked_balance -= amount;
+        }
+    }
 }
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+
+use crate::models::{FundingConfig, FundingRate};
+
+const MAX_FUNDING_HISTORY: usize = 1000;
+
+/// Premium samples since the last funding settlement, and past rates.
+pub struct Funding {
+    premium_sum: Decimal,
+    samples: u32,
+    next_funding_time: Option<i64>,
+    pub history: VecDeque<FundingRate>,
+}
+
+impl Funding {
+    pub fn new() -> Self {
+        Funding {
+            premium_sum: Decimal::ZERO,
+            samples: 0,
+            next_funding_time: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn sample_premium(&mut self, mark_price: Decimal, index_price: Decimal) {
+        if index_price <= Decimal::ZERO {
+            return;
+        }
+        self.premium_sum += (mark_price - index_price) / index_price;
+        self.samples += 1;
+    }
+
+    /// The rate to settle at once the aligned funding interval has elapsed.
+    pub fn settle(
+        &mut self,
+        market: &str,
+        config: &FundingConfig,
+        mark_price: Decimal,
+        index_price: Decimal,
+        now: i64,
+    ) -> Option<FundingRate> {
+        let next_boundary = (now / config.interval_secs + 1) * config.interval_secs;
+        let funding_time = *self.next_funding_time.get_or_insert(next_boundary);
+        if now < funding_time {
+            return None;
+        }
+        self.next_funding_time = Some(next_boundary);
+
+        if self.samples == 0 {
+            return None;
+        }
+        let premium_index = self.premium_sum / Decimal::from(self.samples);
+        self.premium_sum = Decimal::ZERO;
+        self.samples = 0;
+
+        let funding_rate = FundingRate {
+            market: market.to_string(),
+            rate: premium_index.clamp(-config.max_rate, config.max_rate),
+            premium_index,
+            mark_price,
+            index_price,
+            timestamp: funding_time,
+        };
+
+        if self.history.len() == MAX_FUNDING_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(funding_rate.clone());
+        Some(funding_rate)
+    }
+}
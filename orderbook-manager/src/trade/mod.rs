@@ -1,5 +1,20 @@
 pub mod orderbook;
 pub use orderbook::*;
 
+pub mod circuit_breaker;
+pub use circuit_breaker::*;
+
+pub mod price_levels;
+pub use price_levels::*;
+
+pub mod trade_history;
+pub use trade_history::*;
+
+pub mod funding;
+pub use funding::*;
+
+pub mod adl;
+pub use adl::*;
+
 pub mod engine;
 pub use engine::*;
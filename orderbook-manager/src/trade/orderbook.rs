@@ -5,92 +5,249 @@ use chrono::Utc;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
 
 use crate::{
+    constants::EXCHANGE_FEE_ACCOUNT,
     models::{
-        CreateOrderPayload, Depth, GetQuoteResponse, MarginPosition, Order, OrderDetails,
-        OrderSide, PositionType, User,
+        CreateOrderPayload, Depth, Fill, FundingRate, GetQuoteResponse, Iceberg, MarginPosition,
+        MarketSpec, MarketStatus, Order, OrderDetails, OrderSide, OrderType, PositionType,
+        PreventedMatch, ProductType, SelfTradePrevention, TickerPayload, User,
     },
     services::price_service::PriceInfo,
 };
 
+use super::{CircuitBreaker, Funding, PriceLevels, TradeHistory};
+
+pub struct FillResult {
+    pub remaining_qty: Decimal,
+    pub filled_qty: Decimal,
+    pub filled_value: Decimal,
+    pub fee: Decimal,
+    pub fills: Vec<Fill>,
+    pub prevented_matches: Vec<PreventedMatch>,
+}
+
 #[allow(dead_code)]
 pub struct Orderbook {
-    pub bids: Vec<Order>,
-    pub asks: Vec<Order>,
+    pub bids: PriceLevels,
+    pub asks: PriceLevels,
+    pub trigger_orders: Vec<Order>,
     pub base_asset: String,
     pub quote_asset: String,
+    pub spec: MarketSpec,
+    pub status: MarketStatus,
+    pub mark_price: Option<Decimal>,
+    pub index_price: Option<Decimal>,
+    pub circuit_breaker: CircuitBreaker,
+    pub trade_history: TradeHistory,
+    pub funding: Funding,
+    order_index: HashMap<String, (OrderSide, Decimal)>,
 }
 
 impl Orderbook {
-    pub fn new(base_asset: String, quote_asset: String) -> Self {
+    pub fn new(spec: MarketSpec) -> Self {
         Orderbook {
-            bids: Vec::new(),
-            asks: Vec::new(),
-            base_asset,
-            quote_asset,
+            bids: PriceLevels::new(OrderSide::Buy),
+            asks: PriceLevels::new(OrderSide::Sell),
+            trigger_orders: Vec::new(),
+            base_asset: spec.base_asset.clone(),
+            quote_asset: spec.quote_asset.clone(),
+            spec,
+            status: MarketStatus::default(),
+            mark_price: None,
+            index_price: None,
+            circuit_breaker: CircuitBreaker::new(),
+            trade_history: TradeHistory::new(),
+            funding: Funding::new(),
+            order_index: HashMap::new(),
+        }
+    }
+
+    pub fn insert_order(&mut self, order: Order) {
+        self.order_index
+            .insert(order.id.clone(), (order.side.clone(), order.price));
+
+        match order.side {
+            OrderSide::Buy => self.bids.push(order),
+            OrderSide::Sell => self.asks.push(order),
+        }
+    }
+
+    pub fn remove_order(&mut self, order_id: &str) -> Option<Order> {
+        let (side, price) = self.order_index.remove(order_id)?;
+
+        match side {
+            OrderSide::Buy => self.bids.remove(price, order_id),
+            OrderSide::Sell => self.asks.remove(price, order_id),
+        }
+    }
+
+    pub fn remove_user_orders(&mut self, user_id: &str, side: Option<&OrderSide>) -> Vec<Order> {
+        let matches =
+            |order: &Order| order.user_id == user_id && side.is_none_or(|side| &order.side == side);
+
+        let order_ids: Vec<String> = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .filter(|order| matches(order))
+            .map(|order| order.id.clone())
+            .collect();
+
+        let mut removed: Vec<Order> = order_ids
+            .iter()
+            .filter_map(|order_id| self.remove_order(order_id))
+            .collect();
+
+        let (triggers, pending) = std::mem::take(&mut self.trigger_orders)
+            .into_iter()
+            .partition(|order| matches(order));
+        self.trigger_orders = pending;
+        removed.extend::<Vec<Order>>(triggers);
+
+        removed
+    }
+
+    pub fn remove_all_orders(&mut self) -> Vec<Order> {
+        let order_ids: Vec<String> = self.order_index.keys().cloned().collect();
+        let mut removed: Vec<Order> = order_ids
+            .iter()
+            .filter_map(|order_id| self.remove_order(order_id))
+            .collect();
+
+        removed.append(&mut self.trigger_orders);
+        removed
+    }
+
+    pub fn get_order(&self, order_id: &str) -> Option<&Order> {
+        let (side, price) = self.order_index.get(order_id)?;
+
+        match side {
+            OrderSide::Buy => self.bids.get(*price, order_id),
+            OrderSide::Sell => self.asks.get(*price, order_id),
+        }
+    }
+
+    pub fn amend_order(
+        &mut self,
+        order_id: &str,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> Option<Order> {
+        let (side, current_price) = self.order_index.get(order_id)?.clone();
+        let levels = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        let order = levels.get_mut(current_price, order_id)?;
+        if price == order.price && quantity <= order.quantity {
+            order.quantity = quantity;
+            if let Some(iceberg) = &mut order.iceberg {
+                iceberg.visible_quantity = iceberg.visible_quantity.min(quantity);
+            }
+            return Some(order.clone());
+        }
+
+        let mut order = self.remove_order(order_id)?;
+        order.price = price;
+        order.quantity = quantity;
+        order.timestamp = Utc::now().timestamp();
+        if let Some(iceberg) = &mut order.iceberg {
+            *iceberg = Iceberg::new(iceberg.display_quantity, quantity);
         }
+        self.insert_order(order.clone());
+        Some(order)
     }
 
     pub async fn fill_orders(
         &mut self,
+        order_id: &str,
         order: &CreateOrderPayload,
         users: &mut Arc<RwLock<Vec<User>>>,
         base_asset: &str,
         quote_asset: &str,
-    ) -> Decimal {
+    ) -> FillResult {
         let mut remaining_qty = order.quantity;
+        let mut filled_qty = Decimal::ZERO;
+        let mut filled_value = Decimal::ZERO;
+        let mut fills = Vec::new();
+        let mut prevented_matches = Vec::new();
+        let self_trade_mode = self.self_trade_mode(order);
+        let band_limits = self.price_band_limits();
+        let now = Utc::now().timestamp();
 
         match order.side {
             OrderSide::Buy => {
-                for i in 0..self.asks.len() {
-                    if self.asks[i].price > order.price {
+                while let Some(maker) = self.asks.best().cloned() {
+                    if order.order_type != OrderType::Market && maker.price > order.price {
+                        break;
+                    }
+                    if band_limits.is_some_and(|(_, high)| maker.price > high) {
+                        warn!(market = ?self.spec.market, price = ?maker.price, "Matching stopped at the price band");
                         break;
                     }
 
-                    let match_qty = if self.asks[i].quantity > remaining_qty {
-                        remaining_qty
-                    } else {
-                        self.asks[i].quantity
-                    };
+                    if maker.user_id == order.user_id {
+                        let cancelled_qty = self
+                            .prevent_self_trade(&maker, remaining_qty, self_trade_mode, users)
+                            .await;
+                        prevented_matches.push(PreventedMatch {
+                            maker_order_id: maker.id.clone(),
+                            quantity: maker.quantity.min(remaining_qty),
+                            mode: self_trade_mode,
+                        });
 
-                    match (order.is_margin, self.asks[i].is_margin) {
+                        remaining_qty -= cancelled_qty;
+                        if remaining_qty == dec!(0) {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let match_qty = maker.visible_quantity().min(remaining_qty);
+
+                    match (order.is_margin, maker.is_margin) {
                         // Margin buy vs Margin Sell
                         (true, true) => {
                             let buyer_position = MarginPosition {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
                                 user_id: order.user_id.clone(),
                                 position_type: PositionType::Long,
-                                entry_price: self.asks[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
                                 leverage: order.leverage.unwrap(),
-                                collateral: self.calculate_required_margin(
-                                    self.asks[i].price,
-                                    match_qty,
-                                    order.leverage.unwrap(),
-                                ),
+                                collateral: self.order_collateral(order, maker.price, match_qty),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
 
                             let seller_position = MarginPosition {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
-                                user_id: order.user_id.clone(),
+                                user_id: maker.user_id.clone(),
                                 position_type: PositionType::Short,
-                                entry_price: self.asks[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
-                                leverage: self.asks[i].leverage.unwrap(),
+                                leverage: maker.leverage.unwrap(),
                                 collateral: self.calculate_required_margin(
-                                    self.asks[i].price,
+                                    maker.price,
                                     match_qty,
-                                    self.asks[i].leverage.unwrap(),
+                                    maker.leverage.unwrap(),
                                 ),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
 
                             self.net_position(users, &order.user_id, buyer_position)
                                 .await
                                 .unwrap();
-                            self.net_position(users, &order.user_id, seller_position)
+                            self.net_position(users, &maker.user_id, seller_position)
                                 .await
                                 .unwrap();
                         }
@@ -100,24 +257,23 @@ impl Orderbook {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
                                 user_id: order.user_id.clone(),
                                 position_type: PositionType::Long,
-                                entry_price: self.asks[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
                                 leverage: order.leverage.unwrap(),
-                                collateral: self.calculate_required_margin(
-                                    self.asks[i].price,
-                                    match_qty,
-                                    order.leverage.unwrap(),
-                                ),
+                                collateral: self.order_collateral(order, maker.price, match_qty),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
                             self.net_position(users, &order.user_id, buyer_position)
                                 .await
                                 .unwrap();
 
                             self.flip_balance(
-                                &order.user_id,
-                                &self.asks[i].user_id,
-                                self.asks[i].price,
+                                None,
+                                Some(&maker.user_id),
+                                maker.price,
                                 match_qty,
                                 users,
                                 base_asset,
@@ -128,9 +284,9 @@ impl Orderbook {
                         // Spot buy vs Margin sell
                         (false, true) => {
                             self.flip_balance(
-                                &order.user_id,
-                                &self.asks[i].user_id,
-                                self.asks[i].price,
+                                Some(&order.user_id),
+                                None,
+                                maker.price,
                                 match_qty,
                                 users,
                                 base_asset,
@@ -140,28 +296,31 @@ impl Orderbook {
 
                             let seller_position = MarginPosition {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
-                                user_id: self.asks[i].user_id.clone(),
+                                user_id: maker.user_id.clone(),
                                 position_type: PositionType::Short,
-                                entry_price: self.asks[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
-                                leverage: self.asks[i].leverage.unwrap(),
+                                leverage: maker.leverage.unwrap(),
                                 collateral: self.calculate_required_margin(
-                                    self.asks[i].price,
+                                    maker.price,
                                     match_qty,
-                                    self.asks[i].leverage.unwrap(),
+                                    maker.leverage.unwrap(),
                                 ),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
-                            self.net_position(users, &self.asks[i].user_id, seller_position)
+                            self.net_position(users, &maker.user_id, seller_position)
                                 .await
                                 .unwrap();
                         }
                         // Spot Buy vs Spot Sell
                         (false, false) => {
                             self.flip_balance(
-                                &order.user_id,
-                                &self.asks[i].user_id,
-                                self.asks[i].price,
+                                Some(&order.user_id),
+                                Some(&maker.user_id),
+                                maker.price,
                                 match_qty,
                                 users,
                                 base_asset,
@@ -171,11 +330,15 @@ impl Orderbook {
                         }
                     }
                     remaining_qty -= match_qty;
-                    if self.asks[i].quantity == match_qty {
-                        self.asks.remove(i);
-                    } else {
-                        self.asks[i].quantity -= match_qty;
-                    }
+                    filled_qty += match_qty;
+                    filled_value += maker.price * match_qty;
+                    fills.push(
+                        self.record_fill(order_id, order, &maker, match_qty, users, now)
+                            .await,
+                    );
+                    self.circuit_breaker.record_trade(maker.price, now);
+                    self.trade_history.record(maker.price, match_qty, now);
+                    self.reduce_best(&maker.side, match_qty);
 
                     if remaining_qty == dec!(0) {
                         break;
@@ -183,54 +346,73 @@ impl Orderbook {
                 }
             }
             OrderSide::Sell => {
-                for i in 0..self.bids.len() {
-                    if self.bids[i].price < order.price {
+                while let Some(maker) = self.bids.best().cloned() {
+                    if order.order_type != OrderType::Market && maker.price < order.price {
+                        break;
+                    }
+                    if band_limits.is_some_and(|(low, _)| maker.price < low) {
+                        warn!(market = ?self.spec.market, price = ?maker.price, "Matching stopped at the price band");
                         break;
                     }
 
-                    let match_qty = if self.bids[i].quantity > remaining_qty {
-                        remaining_qty
-                    } else {
-                        self.bids[i].quantity
-                    };
+                    if maker.user_id == order.user_id {
+                        let cancelled_qty = self
+                            .prevent_self_trade(&maker, remaining_qty, self_trade_mode, users)
+                            .await;
+                        prevented_matches.push(PreventedMatch {
+                            maker_order_id: maker.id.clone(),
+                            quantity: maker.quantity.min(remaining_qty),
+                            mode: self_trade_mode,
+                        });
+
+                        remaining_qty -= cancelled_qty;
+                        if remaining_qty == dec!(0) {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let match_qty = maker.visible_quantity().min(remaining_qty);
 
-                    match (order.is_margin, self.bids[i].is_margin) {
+                    match (order.is_margin, maker.is_margin) {
                         // margin sell vs margin buy
                         (true, true) => {
                             let seller_position = MarginPosition {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
                                 user_id: order.user_id.clone(),
                                 position_type: PositionType::Short,
-                                entry_price: self.bids[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
                                 leverage: order.leverage.unwrap(),
-                                collateral: self.calculate_required_margin(
-                                    self.bids[i].price,
-                                    match_qty,
-                                    order.leverage.unwrap(),
-                                ),
+                                collateral: self.order_collateral(order, maker.price, match_qty),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
 
                             let buyer_position = MarginPosition {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
-                                user_id: self.bids[i].user_id.clone(),
+                                user_id: maker.user_id.clone(),
                                 position_type: PositionType::Long,
-                                entry_price: self.bids[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
-                                leverage: self.bids[i].leverage.unwrap(),
+                                leverage: maker.leverage.unwrap(),
                                 collateral: self.calculate_required_margin(
-                                    self.bids[i].price,
+                                    maker.price,
                                     match_qty,
-                                    self.bids[i].leverage.unwrap(),
+                                    maker.leverage.unwrap(),
                                 ),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
 
                             self.net_position(users, &order.user_id, seller_position)
                                 .await
                                 .unwrap();
-                            self.net_position(users, &self.bids[i].user_id, buyer_position)
+                            self.net_position(users, &maker.user_id, buyer_position)
                                 .await
                                 .unwrap();
                         }
@@ -240,24 +422,23 @@ impl Orderbook {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
                                 user_id: order.user_id.to_string(),
                                 position_type: PositionType::Short,
-                                entry_price: self.bids[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
                                 leverage: order.leverage.unwrap(),
-                                collateral: self.calculate_required_margin(
-                                    self.bids[i].price,
-                                    match_qty,
-                                    order.leverage.unwrap(),
-                                ),
+                                collateral: self.order_collateral(order, maker.price, match_qty),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
                             self.net_position(users, &order.user_id, seller_position)
                                 .await
                                 .unwrap();
 
                             self.flip_balance(
-                                &self.bids[i].user_id,
-                                &order.user_id,
-                                self.bids[i].price,
+                                Some(&maker.user_id),
+                                None,
+                                maker.price,
                                 match_qty,
                                 users,
                                 base_asset,
@@ -268,9 +449,9 @@ impl Orderbook {
                         // spot sell vs margin buy
                         (false, true) => {
                             self.flip_balance(
-                                &self.bids[i].user_id,
-                                &order.user_id,
-                                self.bids[i].price,
+                                None,
+                                Some(&order.user_id),
+                                maker.price,
                                 match_qty,
                                 users,
                                 base_asset,
@@ -280,28 +461,31 @@ impl Orderbook {
 
                             let buyer_position = MarginPosition {
                                 asset: format!("{}_{}", self.base_asset, self.quote_asset),
-                                user_id: self.bids[i].user_id.clone(),
+                                user_id: maker.user_id.clone(),
                                 position_type: PositionType::Long,
-                                entry_price: self.bids[i].price,
+                                entry_price: maker.price,
                                 size: match_qty,
-                                leverage: self.bids[i].leverage.unwrap(),
+                                leverage: maker.leverage.unwrap(),
                                 collateral: self.calculate_required_margin(
-                                    self.bids[i].price,
+                                    maker.price,
                                     match_qty,
-                                    self.bids[i].leverage.unwrap(),
+                                    maker.leverage.unwrap(),
                                 ),
                                 unrealized_pnl: dec!(0),
+                                adl_rank: None,
+                                liquidation_price: None,
+                                margin_ratio: None,
                             };
 
-                            self.net_position(users, &self.bids[i].user_id, buyer_position)
+                            self.net_position(users, &maker.user_id, buyer_position)
                                 .await
                                 .unwrap();
                         }
                         (false, false) => {
                             self.flip_balance(
-                                &self.bids[i].user_id,
-                                &order.user_id,
-                                self.bids[i].price,
+                                Some(&maker.user_id),
+                                Some(&order.user_id),
+                                maker.price,
                                 match_qty,
                                 users,
                                 base_asset,
@@ -311,11 +495,15 @@ impl Orderbook {
                         }
                     }
                     remaining_qty -= match_qty;
-                    if self.bids[i].quantity == match_qty {
-                        self.bids.remove(i);
-                    } else {
-                        self.bids[i].quantity -= match_qty;
-                    }
+                    filled_qty += match_qty;
+                    filled_value += maker.price * match_qty;
+                    fills.push(
+                        self.record_fill(order_id, order, &maker, match_qty, users, now)
+                            .await,
+                    );
+                    self.circuit_breaker.record_trade(maker.price, now);
+                    self.trade_history.record(maker.price, match_qty, now);
+                    self.reduce_best(&maker.side, match_qty);
 
                     if remaining_qty == dec!(0) {
                         break;
@@ -324,75 +512,280 @@ impl Orderbook {
             }
         }
 
-        remaining_qty
+        FillResult {
+            remaining_qty,
+            filled_qty,
+            filled_value,
+            fee: fills.iter().map(|fill| fill.taker_fee).sum(),
+            fills,
+            prevented_matches,
+        }
+    }
+
+    /// The asset a party pays fees in, the quote asset for margin trades.
+    pub fn fee_asset(&self, side: &OrderSide, is_margin: bool) -> &str {
+        match (side, is_margin) {
+            (OrderSide::Buy, false) => &self.base_asset,
+            _ => &self.quote_asset,
+        }
+    }
+
+    async fn record_fill(
+        &self,
+        taker_order_id: &str,
+        taker: &CreateOrderPayload,
+        maker: &Order,
+        quantity: Decimal,
+        users: &mut Arc<RwLock<Vec<User>>>,
+        now: i64,
+    ) -> Fill {
+        let (taker_fee, maker_fee) = self.charge_fees(taker, maker, quantity, users, now).await;
+
+        Fill {
+            trade_id: Uuid::new_v4().to_string(),
+            market: self.spec.market.clone(),
+            maker_order_id: maker.id.clone(),
+            taker_order_id: taker_order_id.to_string(),
+            price: maker.price,
+            quantity,
+            taker_side: taker.side.clone(),
+            maker_fee,
+            maker_fee_asset: self.fee_asset(&maker.side, maker.is_margin).to_string(),
+            taker_fee,
+            taker_fee_asset: self.fee_asset(&taker.side, taker.is_margin).to_string(),
+            timestamp: now,
+        }
+    }
+
+    /// Charges both parties of a fill, returning `(taker_fee, maker_fee)`.
+    async fn charge_fees(
+        &self,
+        taker: &CreateOrderPayload,
+        maker: &Order,
+        quantity: Decimal,
+        users: &mut Arc<RwLock<Vec<User>>>,
+        now: i64,
+    ) -> (Decimal, Decimal) {
+        let notional = maker.price * quantity;
+        let parties = [
+            (&taker.user_id, &taker.side, taker.is_margin, false),
+            (&maker.user_id, &maker.side, maker.is_margin, true),
+        ];
+        let mut users_guard = users.write().await;
+        let mut fees = (Decimal::ZERO, Decimal::ZERO);
+
+        for (user_id, side, is_margin, is_maker) in parties {
+            let asset = self.fee_asset(side, is_margin);
+            let rebate_pool = users_guard
+                .iter()
+                .find(|u| u.id == EXCHANGE_FEE_ACCOUNT)
+                .map_or(Decimal::ZERO, |u| u.free_balance(asset).max(Decimal::ZERO));
+            let Some(user) = users_guard.iter_mut().find(|u| &u.id == user_id) else {
+                continue;
+            };
+            let (maker_rate, taker_rate) = self.spec.fees.rates(user.rolling_volume(now));
+            let rate = if is_maker { maker_rate } else { taker_rate };
+            // Rebates never overdraw the fee account
+            let fee = match (side, is_margin) {
+                (OrderSide::Buy, false) => quantity * rate,
+                _ => notional * rate,
+            }
+            .max(-rebate_pool);
+
+            user.credit(asset, -fee);
+            user.record_volume(now, notional);
+            if is_maker {
+                fees.1 = fee;
+            } else {
+                fees.0 = fee;
+            }
+
+            if let Some(fee_account) = users_guard
+                .iter_mut()
+                .find(|u| u.id == EXCHANGE_FEE_ACCOUNT)
+            {
+                fee_account.credit(asset, fee);
+            }
+        }
+        fees
+    }
+
+    /// Price range limit orders may trade at.
+    pub fn price_band_limits(&self) -> Option<(Decimal, Decimal)> {
+        let reference = self.circuit_breaker.last_price().or(self.mark_price)?;
+        let band_pct = self.spec.price_band.band_pct;
+
+        Some((
+            reference * (dec!(1) - band_pct),
+            reference * (dec!(1) + band_pct),
+        ))
+    }
+
+    /// Halts the market when recent trades moved past the halt threshold.
+    pub fn trip_circuit_breaker(&mut self, now: i64) -> Option<Decimal> {
+        if self.status != MarketStatus::Trading {
+            return None;
+        }
+
+        let price_move = self
+            .circuit_breaker
+            .price_move(&self.spec.price_band, now)?;
+        self.circuit_breaker.reset_window();
+        self.status = MarketStatus::Halted;
+        self.circuit_breaker.halted_until = Some(now + self.spec.price_band.cooldown_secs);
+        Some(price_move)
+    }
+
+    /// Reopens a market once its circuit breaker cooldown has passed.
+    pub fn resume_after_cooldown(&mut self, now: i64) -> bool {
+        match self.circuit_breaker.halted_until {
+            Some(halted_until) if now >= halted_until => {
+                self.circuit_breaker.halted_until = None;
+                self.status = MarketStatus::Trading;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn self_trade_mode(&self, order: &CreateOrderPayload) -> SelfTradePrevention {
+        order
+            .self_trade_prevention
+            .unwrap_or(self.spec.self_trade_prevention)
+    }
+
+    fn reduce_best(&mut self, side: &OrderSide, quantity: Decimal) {
+        let levels = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        if let Some(removed) = levels.fill_best(quantity) {
+            self.order_index.remove(&removed.id);
+        }
+    }
+
+    async fn prevent_self_trade(
+        &mut self,
+        maker: &Order,
+        remaining_qty: Decimal,
+        mode: SelfTradePrevention,
+        users: &mut Arc<RwLock<Vec<User>>>,
+    ) -> Decimal {
+        let maker_cancelled_qty = match mode {
+            SelfTradePrevention::CancelNewest => Decimal::ZERO,
+            SelfTradePrevention::CancelOldest | SelfTradePrevention::CancelBoth => maker.quantity,
+            SelfTradePrevention::DecrementAndCancel => maker.quantity.min(remaining_qty),
+        };
+
+        if maker_cancelled_qty > Decimal::ZERO {
+            self.reduce_best(&maker.side, maker_cancelled_qty);
+
+            let (ticker, locked_amount) = match maker.side {
+                _ if maker.is_margin => (
+                    &self.quote_asset,
+                    maker.reserved_margin * maker_cancelled_qty / maker.quantity,
+                ),
+                OrderSide::Buy => (&self.quote_asset, maker.price * maker_cancelled_qty),
+                OrderSide::Sell => (&self.base_asset, maker_cancelled_qty),
+            };
+            let mut users_guard = users.write().await;
+            if let Some(balance) = users_guard
+                .iter_mut()
+                .find(|u| u.id == maker.user_id)
+                .and_then(|u| u.balances.iter_mut().find(|b| &b.ticker == ticker))
+            {
+                balance.locked_balance -= locked_amount;
+            }
+        }
+
+        match mode {
+            SelfTradePrevention::CancelNewest | SelfTradePrevention::CancelBoth => remaining_qty,
+            SelfTradePrevention::CancelOldest => Decimal::ZERO,
+            SelfTradePrevention::DecrementAndCancel => maker_cancelled_qty,
+        }
     }
 
     pub fn get_depth(&self) -> Depth {
         let mut depth: HashMap<Decimal, OrderDetails> = HashMap::new();
 
-        for bid in &self.bids {
+        for (price, level) in self.bids.levels() {
+            let quantity: Decimal = level.iter().map(|o| o.visible_quantity()).sum();
             depth
-                .entry(bid.price)
+                .entry(*price)
                 .and_modify(|details| {
-                    details.quantity += bid.quantity;
+                    details.quantity += quantity;
                 })
                 .or_insert(OrderDetails {
                     type_: OrderSide::Buy,
-                    quantity: bid.quantity,
+                    quantity,
                 });
         }
 
-        for ask in &self.asks {
+        for (price, level) in self.asks.levels() {
+            let quantity: Decimal = level.iter().map(|o| o.visible_quantity()).sum();
             depth
-                .entry(ask.price)
+                .entry(*price)
                 .and_modify(|details| {
-                    details.quantity += ask.quantity;
+                    details.quantity += quantity;
                 })
                 .or_insert(OrderDetails {
                     type_: OrderSide::Sell,
-                    quantity: ask.quantity,
+                    quantity,
                 });
         }
 
         Depth { orders: depth }
     }
 
+    /// Only displayed quantity is quoted.
     pub fn get_quote_detail(&self, quantity: Decimal, side: OrderSide) -> GetQuoteResponse {
+        self.quote(quantity, side, |order| order.visible_quantity())
+    }
+
+    /// The cost of a market order, leaving out the user's own orders.
+    pub fn market_order_quote(
+        &self,
+        quantity: Decimal,
+        side: OrderSide,
+        user_id: &str,
+    ) -> GetQuoteResponse {
+        self.quote(quantity, side, |order| {
+            if order.user_id == user_id {
+                Decimal::ZERO
+            } else {
+                order.quantity
+            }
+        })
+    }
+
+    /// Walks the opposite side of the book for `quantity`.
+    fn quote(
+        &self,
+        quantity: Decimal,
+        side: OrderSide,
+        available: impl Fn(&Order) -> Decimal,
+    ) -> GetQuoteResponse {
         let mut remaining_qty = quantity;
         let mut total_cost = Decimal::from(0);
         let mut weighted_avg_price = Decimal::from(0);
 
-        match side {
-            OrderSide::Buy => {
-                for ask in self.asks.iter() {
-                    if remaining_qty == Decimal::from(0) {
-                        break;
-                    }
-
-                    if remaining_qty > ask.quantity {
-                        total_cost += ask.price * ask.quantity;
-                        remaining_qty -= ask.quantity;
-                    } else {
-                        total_cost += ask.price * remaining_qty;
-                        remaining_qty = Decimal::from(0);
-                        break;
-                    }
-                }
+        let resting = match side {
+            OrderSide::Buy => &self.asks,
+            OrderSide::Sell => &self.bids,
+        };
+        for order in resting.iter() {
+            if remaining_qty == Decimal::from(0) {
+                break;
             }
-            OrderSide::Sell => {
-                for bid in self.bids.iter() {
-                    if remaining_qty == Decimal::from(0) {
-                        break;
-                    }
-                    if remaining_qty >= bid.quantity {
-                        total_cost += bid.price * bid.quantity;
-                        remaining_qty -= bid.quantity;
-                    } else {
-                        total_cost += bid.price * remaining_qty;
-                        remaining_qty = Decimal::from(0);
-                        break;
-                    }
-                }
+            let order_qty = available(order);
+            if remaining_qty > order_qty {
+                total_cost += order.price * order_qty;
+                remaining_qty -= order_qty;
+            } else {
+                total_cost += order.price * remaining_qty;
+                remaining_qty = Decimal::from(0);
+                break;
             }
         }
 
@@ -407,10 +800,70 @@ impl Orderbook {
         }
     }
 
+    pub fn would_cross(&self, side: &OrderSide, price: Decimal) -> bool {
+        match side {
+            OrderSide::Buy => self.asks.best_price().is_some_and(|ask| ask <= price),
+            OrderSide::Sell => self.bids.best_price().is_some_and(|bid| bid >= price),
+        }
+    }
+
+    pub fn fillable_quantity(&self, order: &CreateOrderPayload) -> Decimal {
+        let resting = match order.side {
+            OrderSide::Buy => &self.asks,
+            OrderSide::Sell => &self.bids,
+        };
+        let self_trade_mode = self.self_trade_mode(order);
+        let band_limits = self.price_band_limits();
+
+        resting
+            .iter()
+            .take_while(|o| match (&order.side, order.order_type) {
+                (_, OrderType::Market) => true,
+                (OrderSide::Buy, _) => o.price <= order.price,
+                (OrderSide::Sell, _) => o.price >= order.price,
+            })
+            .take_while(|o| band_limits.is_none_or(|(low, high)| o.price >= low && o.price <= high))
+            .take_while(|o| {
+                o.user_id != order.user_id || self_trade_mode == SelfTradePrevention::CancelOldest
+            })
+            .filter(|o| o.user_id != order.user_id)
+            .map(|o| o.quantity)
+            .sum()
+    }
+
+    pub fn take_triggered_orders(&mut self, mark_price: Decimal) -> Vec<Order> {
+        let (triggered, pending) = std::mem::take(&mut self.trigger_orders)
+            .into_iter()
+            .partition(|order| Self::is_triggered(order, mark_price));
+
+        self.trigger_orders = pending;
+        triggered
+    }
+
+    fn is_triggered(order: &Order, mark_price: Decimal) -> bool {
+        let trigger_price = match order.trigger_price {
+            Some(price) => price,
+            None => return false,
+        };
+
+        match (order.order_type, &order.side) {
+            (OrderType::StopMarket | OrderType::StopLimit, OrderSide::Sell) => {
+                mark_price <= trigger_price
+            }
+            (OrderType::StopMarket | OrderType::StopLimit, OrderSide::Buy) => {
+                mark_price >= trigger_price
+            }
+            (OrderType::TakeProfit, OrderSide::Sell) => mark_price >= trigger_price,
+            (OrderType::TakeProfit, OrderSide::Buy) => mark_price <= trigger_price,
+            _ => false,
+        }
+    }
+
+    /// Settles the spot parties of a fill, margin parties are `None`.
     async fn flip_balance(
         &self,
-        buyer_id: &str,
-        seller_id: &str,
+        buyer_id: Option<&str>,
+        seller_id: Option<&str>,
         price: Decimal,
         quantity: Decimal,
         users: &mut Arc<RwLock<Vec<User>>>,
@@ -420,7 +873,7 @@ impl Orderbook {
         let mut users_guard = users.write().await;
         let trade_value = price * quantity;
 
-        if let Some(seller) = users_guard.iter_mut().find(|u| u.id == seller_id) {
+        if let Some(seller) = seller_id.and_then(|id| users_guard.iter_mut().find(|u| u.id == id)) {
             if let Some(base_balance) = seller.balances.iter_mut().find(|b| b.ticker == base_asset)
             {
                 base_balance.locked_balance =
@@ -435,7 +888,7 @@ impl Orderbook {
             }
         }
 
-        if let Some(buyer) = users_guard.iter_mut().find(|u| u.id == buyer_id) {
+        if let Some(buyer) = buyer_id.and_then(|id| users_guard.iter_mut().find(|u| u.id == id)) {
             if let Some(base_balance) = buyer.balances.iter_mut().find(|b| b.ticker == base_asset) {
                 base_balance.balance = base_balance.balance.checked_add(quantity).unwrap();
             }
@@ -451,32 +904,69 @@ impl Orderbook {
         }
     }
 
+    /// Index price plus the book's basis, clamped to `max_basis_pct`.
     pub async fn get_price_info(&self) -> Option<PriceInfo> {
-        let now = Utc::now().timestamp();
-        if self.bids.is_empty() && self.asks.is_empty() {
-            return Some(PriceInfo {
-                last_trade_price: None,
-                mark_price: dec!(100),
-                index_price: None,
-                timestamp: now,
-            });
-        }
-        let best_bid = self.bids.first().map(|o| o.price);
-        let best_ask = self.asks.first().map(|o| o.price);
-        let mark_price = match (best_bid, best_ask) {
-            (Some(b), Some(a)) => (b + a) / dec!(2),
-            (Some(b), None) => b,
-            (None, Some(a)) => a,
-            (None, None) => dec!(100),
+        let last_trade_price = self.trade_history.last_price();
+        let best_bid = self.bids.best_price();
+        let best_ask = self.asks.best_price();
+        let book_price = match (best_bid, best_ask) {
+            (Some(b), Some(a)) => Some((b + a) / dec!(2)),
+            _ => last_trade_price.or(best_bid).or(best_ask),
+        };
+        let mark_price = match (self.index_price, book_price) {
+            (Some(index), Some(book)) => {
+                let max_basis = index * self.spec.max_basis_pct;
+                index + (book - index).clamp(-max_basis, max_basis)
+            }
+            (Some(index), None) => index,
+            (None, book) => book?,
         };
         Some(PriceInfo {
-            last_trade_price: None,
+            last_trade_price,
             mark_price,
-            index_price: None,
-            timestamp: now,
+            index_price: self.index_price,
+            timestamp: Utc::now().timestamp(),
         })
     }
 
+    /// Samples the premium and returns a funding rate once due.
+    pub fn accrue_funding(&mut self, mark_price: Decimal, now: i64) -> Option<FundingRate> {
+        if self.spec.product != ProductType::Perpetual {
+            return None;
+        }
+        let index_price = self.index_price?;
+
+        self.funding.sample_premium(mark_price, index_price);
+        self.funding.settle(
+            &self.spec.market,
+            &self.spec.funding,
+            mark_price,
+            index_price,
+            now,
+        )
+    }
+
+    pub async fn ticker(&self) -> TickerPayload {
+        TickerPayload {
+            market: self.spec.market.clone(),
+            price: self.get_price_info().await,
+            stats: self.trade_history.stats_24h(Utc::now().timestamp()),
+        }
+    }
+
+    /// Margin reserved for a fill, none for reduce-only orders.
+    fn order_collateral(
+        &self,
+        order: &CreateOrderPayload,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> Decimal {
+        if order.reduce_only {
+            return Decimal::ZERO;
+        }
+        self.calculate_required_margin(price, quantity, order.leverage.unwrap())
+    }
+
     fn calculate_required_margin(
         &self,
         price: Decimal,
@@ -486,36 +976,65 @@ impl Orderbook {
         (price * quantity) / Decimal::from(leverage)
     }
 
+    /// Adds a fill to the user's position, reducing the opposite one first.
     async fn net_position(
         &self,
         users: &mut Arc<RwLock<Vec<User>>>,
         user_id: &str,
-        new_position: MarginPosition,
+        mut new_position: MarginPosition,
     ) -> Result<(), &'static str> {
         let mut user_guard = users.write().await;
-        match user_guard.iter_mut().find(|u| u.id == user_id) {
-            Some(user) => {
-                match user.margin_positions.iter_mut().find(|p| {
-                    p.asset == new_position.asset && p.position_type == new_position.position_type
-                }) {
-                    Some(existing_position) => {
-                        let total_size = existing_position.size + new_position.size;
-                        let new_entry_price = ((existing_position.entry_price
-                            * existing_position.size)
-                            + (new_position.entry_price * new_position.size))
-                            / total_size;
-
-                        existing_position.size = total_size;
-                        existing_position.entry_price = new_entry_price;
-                        Ok(())
-                    }
-                    None => {
-                        user.margin_positions.push(new_position);
-                        Ok(())
-                    }
-                }
+        let user = user_guard
+            .iter_mut()
+            .find(|u| u.id == user_id)
+            .ok_or("User not found")?;
+
+        if let Some(existing) = user.margin_positions.iter().find(|p| {
+            p.asset == new_position.asset && p.position_type != new_position.position_type
+        }) {
+            let existing_type = existing.position_type.clone();
+            let closed = existing.size.min(new_position.size);
+            let released_new = new_position.collateral * closed / new_position.size;
+
+            user.close_position(
+                &new_position.asset,
+                &existing_type,
+                closed,
+                new_position.entry_price,
+                &self.quote_asset,
+            );
+            new_position.size -= closed;
+            new_position.collateral -= released_new;
+
+            if let Some(quote_balance) = user
+                .balances
+                .iter_mut()
+                .find(|b| b.ticker == self.quote_asset)
+            {
+                quote_balance.locked_balance -= released_new;
+            }
+        }
+
+        if new_position.size.is_zero() {
+            return Ok(());
+        }
+        user.margin_used += new_position.collateral;
+
+        match user.margin_positions.iter_mut().find(|p| {
+            p.asset == new_position.asset && p.position_type == new_position.position_type
+        }) {
+            Some(existing_position) => {
+                let total_size = existing_position.size + new_position.size;
+                let new_entry_price = ((existing_position.entry_price * existing_position.size)
+                    + (new_position.entry_price * new_position.size))
+                    / total_size;
+
+                existing_position.size = total_size;
+                existing_position.entry_price = new_entry_price;
+                existing_position.collateral += new_position.collateral;
             }
-            None => Err("User not found"),
+            None => user.margin_positions.push(new_position),
         }
+        Ok(())
     }
 }
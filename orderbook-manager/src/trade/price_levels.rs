@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use rust_decimal::Decimal;
+
+use crate::models::{Order, OrderSide};
+
+pub struct PriceLevels {
+    side: OrderSide,
+    levels: BTreeMap<Decimal, VecDeque<Order>>,
+    len: usize,
+}
+
+impl PriceLevels {
+    pub fn new(side: OrderSide) -> Self {
+        PriceLevels {
+            side,
+            levels: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn best_price(&self) -> Option<Decimal> {
+        match self.side {
+            OrderSide::Buy => self.levels.keys().next_back().copied(),
+            OrderSide::Sell => self.levels.keys().next().copied(),
+        }
+    }
+
+    pub fn best(&self) -> Option<&Order> {
+        let price = self.best_price()?;
+        self.levels.get(&price).and_then(|level| level.front())
+    }
+
+    pub fn levels(&self) -> Box<dyn Iterator<Item = (&Decimal, &VecDeque<Order>)> + '_> {
+        match self.side {
+            OrderSide::Buy => Box::new(self.levels.iter().rev()),
+            OrderSide::Sell => Box::new(self.levels.iter()),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Order> + '_ {
+        self.levels().flat_map(|(_, level)| level.iter())
+    }
+
+    pub fn push(&mut self, order: Order) {
+        self.levels.entry(order.price).or_default().push_back(order);
+        self.len += 1;
+    }
+
+    pub fn remove(&mut self, price: Decimal, order_id: &str) -> Option<Order> {
+        let level = self.levels.get_mut(&price)?;
+        let index = level.iter().position(|o| o.id == order_id)?;
+        let order = level.remove(index);
+
+        if level.is_empty() {
+            self.levels.remove(&price);
+        }
+        self.len -= 1;
+        order
+    }
+
+    pub fn get(&self, price: Decimal, order_id: &str) -> Option<&Order> {
+        self.levels.get(&price)?.iter().find(|o| o.id == order_id)
+    }
+
+    pub fn get_mut(&mut self, price: Decimal, order_id: &str) -> Option<&mut Order> {
+        self.levels
+            .get_mut(&price)?
+            .iter_mut()
+            .find(|o| o.id == order_id)
+    }
+
+    pub fn fill_best(&mut self, quantity: Decimal) -> Option<Order> {
+        let price = self.best_price()?;
+        let level = self.levels.get_mut(&price)?;
+        let best = level.front_mut()?;
+
+        if best.quantity > quantity {
+            best.reserved_margin -= best.reserved_margin * quantity / best.quantity;
+            best.quantity -= quantity;
+
+            // Refill an exhausted iceberg slice and requeue it
+            if let Some(iceberg) = &mut best.iceberg {
+                iceberg.visible_quantity -= quantity.min(iceberg.visible_quantity);
+                if iceberg.visible_quantity.is_zero() {
+                    iceberg.visible_quantity = iceberg.display_quantity.min(best.quantity);
+                    let replenished = level.pop_front().unwrap();
+                    level.push_back(replenished);
+                }
+            }
+            return None;
+        }
+
+        let filled = level.pop_front();
+        if level.is_empty() {
+            self.levels.remove(&price);
+        }
+        self.len -= 1;
+        filled
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+
+use crate::models::Stats24h;
+
+const WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// Trades of the last 24 hours of a market, kept for its ticker statistics.
+pub struct TradeHistory {
+    trades: VecDeque<(i64, Decimal, Decimal)>,
+    last_price: Option<Decimal>,
+}
+
+impl TradeHistory {
+    pub fn new() -> Self {
+        TradeHistory {
+            trades: VecDeque::new(),
+            last_price: None,
+        }
+    }
+
+    /// The price of the most recent trade, even if it fell out of the window.
+    pub fn last_price(&self) -> Option<Decimal> {
+        self.last_price
+    }
+
+    pub fn record(&mut self, price: Decimal, quantity: Decimal, timestamp: i64) {
+        self.trades.push_back((timestamp, price, quantity));
+        self.last_price = Some(price);
+
+        while self
+            .trades
+            .front()
+            .is_some_and(|(trade_time, _, _)| *trade_time <= timestamp - WINDOW_SECS)
+        {
+            self.trades.pop_front();
+        }
+    }
+
+    pub fn stats_24h(&self, now: i64) -> Stats24h {
+        let mut stats = Stats24h::default();
+
+        for (_, price, quantity) in self
+            .trades
+            .iter()
+            .filter(|(trade_time, _, _)| *trade_time > now - WINDOW_SECS)
+        {
+            stats.open.get_or_insert(*price);
+            stats.high = Some(stats.high.map_or(*price, |high| high.max(*price)));
+            stats.low = Some(stats.low.map_or(*price, |low| low.min(*price)));
+            stats.close = Some(*price);
+            stats.base_volume += quantity;
+            stats.quote_volume += price * quantity;
+            stats.trade_count += 1;
+        }
+        stats
+    }
+}
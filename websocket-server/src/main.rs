@@ -30,6 +30,14 @@ async fn main() {
 
         pubsub.psubscribe("trade@*").await.unwrap();
         pubsub.psubscribe("depth@*").await.unwrap();
+        pubsub.psubscribe("status@*").await.unwrap();
+        pubsub.psubscribe("funding@*").await.unwrap();
+        pubsub.psubscribe("liquidation@*").await.unwrap();
+        pubsub.psubscribe("adl@*").await.unwrap();
+        pubsub.psubscribe("position@*").await.unwrap();
+        pubsub.psubscribe("margin_call@*").await.unwrap();
+        pubsub.psubscribe("ticker@*").await.unwrap();
+        pubsub.psubscribe("trigger_rejected@*").await.unwrap();
         info!("Redis subscription started");
 
         loop {